@@ -0,0 +1,295 @@
+use std::cmp;
+use std::sync::Mutex;
+
+use time::Duration;
+
+use specs::{World, Join};
+
+use tile::TileMap;
+
+use player::{Player, Direction, RUN_TILES};
+
+use flock;
+
+use input::Action;
+
+use ecs_entities::{Utterance, Position, Avatar};
+
+use ecs_systems::{DogCommand, GameClock, EventLog, Hud, MapMode};
+
+use savegame::{SaveGame, DEFAULT_SAVE_PATH};
+
+/// Screen cells the map cursor jumps with a run key.
+const MAP_RUN_CELLS: usize = 8;
+
+fn passable(map: &TileMap, x: usize, y: usize) -> bool {
+    map.get_tile(x, y).is_some_and(|tile| flock::tile_is_passable(map, tile))
+}
+
+/// Turns the player to face a direction and steps that way if the tile is
+/// on the map and passable. Diagonal steps can't squeeze between two
+/// impassable tiles. Returns whether the player moved.
+fn step(player: &mut Player, map: &Mutex<TileMap>, direction: Direction) -> bool {
+    player.facing = direction;
+    let (x, y) = match player.neighbour(direction) {
+        Some(tile) => tile,
+        None => return false,
+    };
+    let map = map.lock().unwrap();
+    if !passable(&map, x, y) {
+        return false;
+    }
+    if x != player.x && y != player.y && !passable(&map, x, player.y) && !passable(&map, player.x, y) {
+        return false;
+    }
+    player.x = x;
+    player.y = y;
+    true
+}
+
+/// Runs several tiles in a direction while stamina lasts. Out of breath, a
+/// run is just a step.
+fn run(player: &mut Player, map: &Mutex<TileMap>, direction: Direction) {
+    for tile in 0..RUN_TILES {
+        if tile > 0 && !player.can_run() {
+            break;
+        }
+        if !step(player, map, direction) {
+            break;
+        }
+        player.tire();
+    }
+}
+
+/// Builds or knocks down a fence or gate in front of the player, unless
+/// something is standing there; it would be stuck inside.
+fn toggle_build(player: &Player, map: &Mutex<TileMap>, world: &Mutex<World>, gate: bool) {
+    if let Some((x, y)) = player.facing_tile() {
+        let occupied = {
+            let world = world.lock().unwrap();
+            let positions = world.read::<Position>();
+            let utterances = world.read::<Utterance>();
+            let avatars = world.read::<Avatar>();
+            // Speech and the player's own stand-in don't get in the way.
+            (&positions, !&utterances, !&avatars).join().any(|(position, _, _)| {
+                position.x.round() as usize == x && position.y.round() as usize == y
+            })
+        };
+        if !occupied {
+            let mut map = map.lock().unwrap();
+            if gate {
+                flock::toggle_gate(&mut map, x, y);
+            } else {
+                flock::toggle_fence(&mut map, x, y);
+            }
+        }
+    }
+}
+
+/// Moves the map cursor instead of the player while the map is open.
+/// Returns whether it was.
+fn pan_map(map: &Mutex<TileMap>, world: &Mutex<World>, direction: Direction, cells: usize) -> bool {
+    let world = world.lock().unwrap();
+    let mut hud = world.write_resource::<Hud>();
+    match hud.map_mode {
+        Some(ref mut map_mode) => {
+            let map = map.lock().unwrap();
+            map_mode.pan(direction, cells, map.width, map.height);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Carries out an action on the game. Locks the player, then the world,
+/// then the map, as the other threads do. Quitting is left to the caller.
+pub fn perform(action: Action, map: &Mutex<TileMap>, player: &Mutex<Player>, world: &Mutex<World>) {
+    let mut player = player.lock().unwrap();
+    let panned = match action {
+        Action::Move(direction) => pan_map(map, world, direction, 1),
+        Action::Run(direction) => pan_map(map, world, direction, MAP_RUN_CELLS),
+        _ => false,
+    };
+    if panned {
+        return;
+    }
+    match action {
+        Action::Move(direction) => {
+            step(&mut player, map, direction);
+        }
+        Action::Run(direction) => run(&mut player, map, direction),
+        Action::ToggleFence => toggle_build(&player, map, world, false),
+        Action::ToggleGate => toggle_build(&player, map, world, true),
+        Action::ComeBye => *world.lock().unwrap().write_resource::<DogCommand>() = DogCommand::ComeBye,
+        Action::AwayToMe => *world.lock().unwrap().write_resource::<DogCommand>() = DogCommand::AwayToMe,
+        Action::WalkUp => *world.lock().unwrap().write_resource::<DogCommand>() = DogCommand::WalkUp,
+        Action::LieDown => *world.lock().unwrap().write_resource::<DogCommand>() = DogCommand::LieDown,
+        Action::ThatllDo => *world.lock().unwrap().write_resource::<DogCommand>() = DogCommand::ThatllDo,
+        Action::TogglePause => world.lock().unwrap().write_resource::<GameClock>().toggle_pause(),
+        Action::SpeedUp => world.lock().unwrap().write_resource::<GameClock>().speed_up(),
+        Action::SlowDown => world.lock().unwrap().write_resource::<GameClock>().slow_down(),
+        Action::Save => {
+            let world = world.lock().unwrap();
+            let saved = {
+                let map = map.lock().unwrap();
+                SaveGame::capture(&map, &player, &world).write(DEFAULT_SAVE_PATH)
+            };
+            let now = world.read_resource::<GameClock>().now;
+            world.write_resource::<EventLog>().push(now, match saved {
+                Ok(()) => format!("Saved to {}", DEFAULT_SAVE_PATH),
+                Err(error) => format!("Couldn't save: {}", error),
+            });
+        }
+        Action::Load => {
+            let mut world = world.lock().unwrap();
+            let types = map.lock().unwrap().types.clone();
+            match SaveGame::read(DEFAULT_SAVE_PATH).and_then(|save| save.to_tilemap(types).map(|loaded| (save, loaded))) {
+                Ok((save, loaded)) => {
+                    map.lock().unwrap().replace(loaded);
+                    *player = save.to_player();
+                    save.restore_world(&mut world);
+                    let now = world.read_resource::<GameClock>().now;
+                    world.write_resource::<EventLog>().push(now, format!("Loaded {}", DEFAULT_SAVE_PATH));
+                }
+                Err(error) => {
+                    let now = world.read_resource::<GameClock>().now;
+                    world.write_resource::<EventLog>().push(now, format!("Couldn't load: {}", error));
+                }
+            }
+        }
+        Action::Speak => {
+            let mut world = world.lock().unwrap();
+            let avatar = flock::avatar(&mut world);
+            flock::say(&mut world, avatar, "Howdy", Duration::seconds(5));
+        }
+        Action::LogOlder => {
+            let world = world.lock().unwrap();
+            let entries = world.read_resource::<EventLog>().0.len();
+            let mut hud = world.write_resource::<Hud>();
+            hud.log_scroll = cmp::min(hud.log_scroll + 1, entries.saturating_sub(1));
+        }
+        Action::LogNewer => {
+            let world = world.lock().unwrap();
+            let mut hud = world.write_resource::<Hud>();
+            hud.log_scroll = hud.log_scroll.saturating_sub(1);
+        }
+        Action::ToggleMinimap => {
+            let world = world.lock().unwrap();
+            let mut hud = world.write_resource::<Hud>();
+            hud.minimap = !hud.minimap;
+        }
+        Action::ToggleMap => {
+            let world = world.lock().unwrap();
+            let mut hud = world.write_resource::<Hud>();
+            hud.map_mode = match hud.map_mode {
+                Some(_) => None,
+                None => Some(MapMode::new(player.x, player.y)),
+            };
+        }
+        Action::ZoomIn => {
+            let world = world.lock().unwrap();
+            let mut hud = world.write_resource::<Hud>();
+            if let Some(ref mut map_mode) = hud.map_mode {
+                map_mode.zoom_in();
+            }
+        }
+        Action::ZoomOut => {
+            let world = world.lock().unwrap();
+            let mut hud = world.write_resource::<Hud>();
+            if let Some(ref mut map_mode) = hud.map_mode {
+                map_mode.zoom_out();
+            }
+        }
+        Action::Describe => {
+            let world = world.lock().unwrap();
+            let lines = flock::describe_surroundings(&world, &map.lock().unwrap(), player.x, player.y);
+            let now = world.read_resource::<GameClock>().now;
+            let mut log = world.write_resource::<EventLog>();
+            for line in lines {
+                log.push(now, line);
+            }
+        }
+        Action::Export => world.lock().unwrap().write_resource::<Hud>().export = true,
+        Action::Help => {
+            let world = world.lock().unwrap();
+            let mut hud = world.write_resource::<Hud>();
+            hud.help = !hud.help;
+        }
+        Action::Wait | Action::Quit => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tile::{Tile, TileMap};
+
+    use tile_types::TileTypes;
+
+    use player::{Player, Direction, RUN_STAMINA_PER_TILE, RUN_TILES};
+
+    use super::{run, step};
+
+    /// A map drawn as rows of text: '~' is water and anything else grass.
+    fn drawn_map(rows: &[&str]) -> Mutex<TileMap> {
+        let types = Arc::new(TileTypes::default());
+        let mut map = TileMap::new(rows[0].len(), rows.len(), 0, types.clone());
+        map.fill_tiles(|x, y| {
+            let id = if rows[y].as_bytes()[x] == b'~' { "water-shallow" } else { "grass-plain" };
+            Tile { x, y, style: types.style(id), rand_offset: 0.0 }
+        });
+        Mutex::new(map)
+    }
+
+    fn player_at(map: &Mutex<TileMap>, x: usize, y: usize) -> Player {
+        let map = map.lock().unwrap();
+        Player::new(x, y, map.width - 1, map.height - 1)
+    }
+
+    #[test]
+    fn diagonals_cannot_squeeze_between_water() {
+        let map = drawn_map(&[
+            ".~.",
+            "~..",
+        ]);
+        let mut player = player_at(&map, 0, 0);
+        assert!(!step(&mut player, &map, Direction::SouthEast));
+        assert_eq!((player.x, player.y), (0, 0));
+        assert_eq!(player.facing, Direction::SouthEast);
+    }
+
+    #[test]
+    fn diagonals_can_pass_one_corner_of_water() {
+        let map = drawn_map(&[
+            ".~.",
+            "...",
+        ]);
+        let mut player = player_at(&map, 0, 0);
+        assert!(step(&mut player, &map, Direction::SouthEast));
+        assert_eq!((player.x, player.y), (1, 1));
+    }
+
+    #[test]
+    fn runs_spend_stamina_per_tile() {
+        let map = drawn_map(&["........"]);
+        let mut player = player_at(&map, 0, 0);
+        let stamina = player.stamina;
+        run(&mut player, &map, Direction::East);
+        assert_eq!(player.x, RUN_TILES);
+        assert_eq!(player.stamina, stamina - RUN_TILES as f32 * RUN_STAMINA_PER_TILE);
+    }
+
+    #[test]
+    fn runs_stop_when_out_of_breath() {
+        let map = drawn_map(&["........"]);
+        let mut player = player_at(&map, 0, 0);
+        player.stamina = RUN_STAMINA_PER_TILE * 2.0;
+        run(&mut player, &map, Direction::East);
+        assert_eq!(player.x, 2);
+        assert!(!player.can_run());
+        // Out of breath, a run is still a step.
+        run(&mut player, &map, Direction::East);
+        assert_eq!(player.x, 3);
+    }
+}
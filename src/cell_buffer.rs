@@ -0,0 +1,121 @@
+/// One character cell of the screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub glyph: char,
+    pub fg: [u8; 3],
+    pub bg: [u8; 3],
+}
+
+impl Cell {
+    pub fn new(glyph: char, fg: [u8; 3], bg: [u8; 3]) -> Cell {
+        Cell {
+            glyph,
+            fg,
+            bg,
+        }
+    }
+
+    pub fn blank() -> Cell {
+        Cell::new(' ', [255, 255, 255], [0, 0, 0])
+    }
+}
+
+/// A screen's worth of cells, drawn into a frame at a time. Anything drawn
+/// outside the buffer is dropped, so callers don't need to clip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellBuffer {
+    pub width: usize,
+    pub height: usize,
+    cells: Vec<Cell>,
+}
+
+impl CellBuffer {
+    pub fn new(width: usize, height: usize) -> CellBuffer {
+        CellBuffer {
+            width,
+            height,
+            cells: vec![Cell::blank(); width * height],
+        }
+    }
+
+    /// Resizes the buffer and blanks every cell.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.cells = vec![Cell::blank(); width * height];
+    }
+
+    pub fn clear(&mut self) {
+        self.fill(Cell::blank());
+    }
+
+    pub fn fill(&mut self, cell: Cell) {
+        for existing in &mut self.cells {
+            *existing = cell;
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<&Cell> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(&self.cells[y * self.width + x])
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, cell: Cell) {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x] = cell;
+        }
+    }
+
+    /// Writes a line of text from a cell rightwards, cutting it off at the
+    /// edge of the buffer.
+    pub fn put_str(&mut self, x: usize, y: usize, text: &str, fg: [u8; 3], bg: [u8; 3]) {
+        for (offset, glyph) in text.chars().enumerate() {
+            self.set(x + offset, y, Cell::new(glyph, fg, bg));
+        }
+    }
+
+    /// A copy of a rectangle of the buffer. Any of it hanging off the edge
+    /// is blank.
+    pub fn region(&self, x: usize, y: usize, width: usize, height: usize) -> CellBuffer {
+        let mut region = CellBuffer::new(width, height);
+        for row in 0..height {
+            for column in 0..width {
+                if let Some(&cell) = self.get(x + column, y + row) {
+                    region.set(column, row, cell);
+                }
+            }
+        }
+        region
+    }
+
+    /// Rows of cells, top to bottom.
+    pub fn rows(&self) -> ::std::slice::Chunks<'_, Cell> {
+        self.cells.chunks(self.width.max(1))
+    }
+
+    /// The glyphs of every row as plain text, without trailing spaces.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for row in self.rows() {
+            let line: String = row.iter().map(|cell| cell.glyph).collect();
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CellBuffer;
+
+    #[test]
+    fn text_trims_trailing_spaces() {
+        let mut buffer = CellBuffer::new(6, 3);
+        buffer.put_str(1, 0, "ab", [255, 255, 255], [0, 0, 0]);
+        buffer.put_str(0, 2, "c  d", [255, 255, 255], [0, 0, 0]);
+        assert_eq!(buffer.text(), " ab\n\nc  d\n");
+    }
+}
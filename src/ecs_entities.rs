@@ -1,13 +1,107 @@
-use time::Tm;
-use specs::{Component, VecStorage};
+use time::Duration;
 
-#[derive(Debug)]
+use savegame;
+use specs::{Component, Entity, VecStorage, NullStorage};
+
+/// Something said out loud. Lives on its own entity, whose `Position`
+/// follows the speaker's while they're around.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Utterance {
-    // utterer: &Entity,
+    /// Who said it. Not saved; a bubble whose speaker is gone stays where
+    /// it was last said.
+    #[serde(skip)]
+    pub speaker: Option<Entity>,
     pub text: String,
-    pub dead_at: Tm,
+    /// Game time at which the utterance fades away.
+    #[serde(with = "savegame::milliseconds")]
+    pub dead_at: Duration,
 }
 
 impl Component for Utterance {
     type Storage = VecStorage<Self>;
 }
+
+/// Position in map tiles. Fractional, so slow movers can cross a tile over
+/// several ticks; round to get the tile they're standing on.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Component for Position {
+    type Storage = VecStorage<Self>;
+}
+
+/// Velocity in tiles per tick.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Velocity {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Component for Velocity {
+    type Storage = VecStorage<Self>;
+}
+
+/// Direction faced, in radians. Kept separately from velocity so a sheep
+/// standing still still faces somewhere.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Heading(pub f32);
+
+impl Component for Heading {
+    type Storage = VecStorage<Self>;
+}
+
+#[derive(Debug, Default)]
+pub struct Sheep;
+
+impl Component for Sheep {
+    type Storage = NullStorage<Self>;
+}
+
+/// A sheepdog. `step` is the tile it's currently walking to, picked by the
+/// dog system one tile at a time so it can path around obstacles.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Dog {
+    pub step: Option<(usize, usize)>,
+}
+
+impl Component for Dog {
+    type Storage = VecStorage<Self>;
+}
+
+/// The player's stand-in among the entities, kept on the player's tile, so
+/// the player can be pointed at like anything else.
+#[derive(Debug, Default)]
+pub struct Avatar;
+
+impl Component for Avatar {
+    type Storage = NullStorage<Self>;
+}
+
+/// Marks anything sheep run away from, besides the player.
+#[derive(Debug, Default)]
+pub struct Threat;
+
+impl Component for Threat {
+    type Storage = NullStorage<Self>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WolfState {
+    /// Heading for the loneliest sheep it can find.
+    Hunting,
+    /// Running from the player or a dog; `ticks` counts down until it's gone.
+    Fleeing { ticks: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Wolf {
+    pub state: WolfState,
+    pub step: Option<(usize, usize)>,
+}
+
+impl Component for Wolf {
+    type Storage = VecStorage<Self>;
+}
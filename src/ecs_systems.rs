@@ -1,22 +1,1201 @@
-use specs::{ReadStorage, Fetch, System, Entities};
-use ecs_entities::Utterance;
-use time::Tm;
+use std::f32::consts::PI;
+use std::cmp;
+use std::fmt;
+use std::sync::{Arc, Mutex};
 
-pub struct GameTimeNow(pub Tm);
+use rand::{IsaacRng, Rng};
+use specs::{ReadStorage, WriteStorage, Fetch, FetchMut, System, Entities, World, RunNow};
+use ecs_entities::{Utterance, Position, Velocity, Heading, Sheep, Dog, Threat, Wolf, WolfState, Avatar};
+use time::Duration;
+
+use tile::{TileMap, TileStyle};
+
+use tile_types::TileTypes;
+
+use player::Direction;
+
+use flock;
+
+use savegame;
+
+/// Length of one simulation tick, in game time.
+const TICK_MICROSECONDS: i64 = 16_667;
+/// The speeds the clock can run at, slowest first.
+const TIME_SCALES: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_TIME_SCALE: usize = 2;
+/// Seconds of game time from one dawn to the next. The flock should be
+/// penned by nightfall.
+pub const DAY_LENGTH: i64 = 300;
+
+/// Cap on ticks run to catch up in one go, so a slow machine at high speed
+/// drops game time rather than falling ever further behind.
+const MAX_CATCH_UP_TICKS: i64 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DayPhase {
+    Dawn,
+    Noon,
+    Dusk,
+    Night,
+}
+
+impl fmt::Display for DayPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            DayPhase::Dawn => "dawn",
+            DayPhase::Noon => "noon",
+            DayPhase::Dusk => "dusk",
+            DayPhase::Night => "night",
+        })
+    }
+}
+
+/// What the sky is doing. It drifts from one kind to the next rather than
+/// jumping, so rain always comes out of cloud.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Weather {
+    #[default]
+    Clear,
+    Cloudy,
+    Rain,
+    Fog,
+}
+
+impl fmt::Display for Weather {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Weather::Clear => "clear",
+            Weather::Cloudy => "cloudy",
+            Weather::Rain => "rain",
+            Weather::Fog => "fog",
+        })
+    }
+}
+
+/// The in-game clock. It only moves when the simulation ticks, so pausing or
+/// changing speed pauses or rescales everything that's timed against it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameClock {
+    /// Game time since the world began.
+    #[serde(with = "savegame::milliseconds")]
+    pub now: Duration,
+    pub paused: bool,
+    time_scale: usize,
+    /// Game time owed to the simulation but not yet ticked through.
+    #[serde(skip)]
+    owed_microseconds: i64,
+}
+
+impl Default for GameClock {
+    fn default() -> GameClock {
+        GameClock {
+            now: Duration::zero(),
+            paused: false,
+            time_scale: NORMAL_TIME_SCALE,
+            owed_microseconds: 0,
+        }
+    }
+}
+
+impl GameClock {
+    pub fn time_scale(&self) -> f32 {
+        TIME_SCALES[self.time_scale]
+    }
+
+    /// Accounts for a step of real time, returning how many fixed ticks the
+    /// simulation should run to catch up with it.
+    pub fn advance(&mut self, real: Duration) -> u32 {
+        if self.paused {
+            return 0;
+        }
+        let real_microseconds = real.num_microseconds().unwrap_or(0);
+        self.owed_microseconds += (real_microseconds as f32 * self.time_scale()) as i64;
+        let ticks = self.owed_microseconds / TICK_MICROSECONDS;
+        self.owed_microseconds -= ticks * TICK_MICROSECONDS;
+        cmp::min(ticks, MAX_CATCH_UP_TICKS) as u32
+    }
+
+    /// How far through the current day it is, from 0 at dawn up to 1.
+    pub fn time_of_day(&self) -> f32 {
+        let day_milliseconds = DAY_LENGTH * 1000;
+        (self.now.num_milliseconds() % day_milliseconds) as f32 / day_milliseconds as f32
+    }
+
+    /// A game time as the day and time on a 24 hour clock. The game starts
+    /// at dawn, six in the morning, and the date changes at midnight.
+    pub fn timestamp(at: Duration) -> String {
+        let day_milliseconds = DAY_LENGTH * 1000;
+        let since_midnight = at.num_milliseconds() + day_milliseconds / 4;
+        let day = since_midnight / day_milliseconds + 1;
+        let minutes = (since_midnight % day_milliseconds) * 24 * 60 / day_milliseconds;
+        format!("day {} {:02}:{:02}", day, minutes / 60, minutes % 60)
+    }
+
+    /// The day the game is on, counting from 1.
+    pub fn day_number(&self) -> u32 {
+        (self.now.num_seconds() / DAY_LENGTH) as u32 + 1
+    }
+
+    pub fn day_phase(&self) -> DayPhase {
+        match self.time_of_day() {
+            t if t < 0.15 => DayPhase::Dawn,
+            t if t < 0.65 => DayPhase::Noon,
+            t if t < 0.75 => DayPhase::Dusk,
+            _ => DayPhase::Night,
+        }
+    }
+
+    /// Moves game time on by one tick.
+    pub fn tick(&mut self) {
+        self.now = self.now + Duration::microseconds(TICK_MICROSECONDS);
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn speed_up(&mut self) {
+        self.time_scale = cmp::min(self.time_scale + 1, TIME_SCALES.len() - 1);
+    }
+
+    pub fn slow_down(&mut self) {
+        self.time_scale = self.time_scale.saturating_sub(1);
+    }
+}
+
+/// A world with every component registered and the game's resources at
+/// their starting values.
+pub fn new_world() -> World {
+    let mut world = World::new();
+    world.register::<Utterance>();
+    world.register::<Position>();
+    world.register::<Velocity>();
+    world.register::<Heading>();
+    world.register::<Sheep>();
+    world.register::<Dog>();
+    world.register::<Threat>();
+    world.register::<Wolf>();
+    world.register::<Avatar>();
+    world.add_resource(GameClock::default());
+    world.add_resource(DogCommand::ThatllDo);
+    world.add_resource(Enclosure::default());
+    world.add_resource(PenCount::default());
+    world.add_resource(EventLog::default());
+    world.add_resource(Weather::default());
+    world.add_resource(Hud::default());
+    world.add_resource(Minimap::default());
+    world.add_resource(Reseed::default());
+    world
+}
+
+/// The player's tile, copied in from `Player` each tick so systems don't
+/// need to lock it.
+pub struct PlayerPosition(pub Position);
+
+/// A message in the event log, stamped with the game time it happened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    #[serde(with = "savegame::milliseconds")]
+    pub at: Duration,
+    pub text: String,
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}  {}", GameClock::timestamp(self.at), self.text)
+    }
+}
+
+/// Game events worth telling the player about, oldest first.
+#[derive(Default)]
+pub struct EventLog(pub Vec<LogEntry>);
+
+impl EventLog {
+    pub fn push<S: Into<String>>(&mut self, at: Duration, message: S) {
+        self.0.push(LogEntry { at, text: message.into() });
+    }
+}
+
+/// What the player has chosen to show over the map. Not part of a save.
+#[derive(Default)]
+pub struct Hud {
+    pub help: bool,
+    pub minimap: bool,
+    /// How many of the newest log entries are scrolled out of sight.
+    pub log_scroll: usize,
+    /// Set while the whole screen is given over to the map.
+    pub map_mode: Option<MapMode>,
+    /// Set to have the next frame drawn written out to files.
+    pub export: bool,
+}
+
+/// How far the map can zoom out, in map tiles across a screen cell.
+const MAP_ZOOMS: [usize; 4] = [1, 2, 4, 8];
+
+/// The map as browsed away from the player: how far out it's zoomed, and
+/// the tile under the cursor in the middle of the screen.
+#[derive(Debug, Clone, Copy)]
+pub struct MapMode {
+    pub zoom: usize,
+    pub cursor: (usize, usize),
+}
+
+impl MapMode {
+    /// Opens the map a step out from normal, with the cursor on a tile.
+    pub fn new(x: usize, y: usize) -> MapMode {
+        MapMode {
+            zoom: MAP_ZOOMS[1],
+            cursor: (x, y),
+        }
+    }
+
+    pub fn zoom_in(&mut self) {
+        self.zoom = MAP_ZOOMS.iter().rev().cloned().find(|&zoom| zoom < self.zoom).unwrap_or(MAP_ZOOMS[0]);
+    }
+
+    pub fn zoom_out(&mut self) {
+        self.zoom = MAP_ZOOMS.iter().cloned().find(|&zoom| zoom > self.zoom).unwrap_or(MAP_ZOOMS[MAP_ZOOMS.len() - 1]);
+    }
+
+    /// Moves the cursor some screen cells in a direction, stopping at the
+    /// edges of a map this big.
+    pub fn pan(&mut self, direction: Direction, cells: usize, map_width: usize, map_height: usize) {
+        let (dx, dy) = direction.offset();
+        let across = (cells * self.zoom) as isize;
+        let down = (cells * if self.zoom > 1 { self.zoom * 2 } else { 1 }) as isize;
+        let x = (self.cursor.0 as isize + dx * across).clamp(0, map_width as isize - 1);
+        let y = (self.cursor.1 as isize + dy * down).clamp(0, map_height as isize - 1);
+        self.cursor = (x as usize, y as usize);
+    }
+}
 
 pub struct UtteranceSystem;
 
 impl<'a> System<'a> for UtteranceSystem {
     type SystemData = (Entities<'a>,
                        ReadStorage<'a, Utterance>,
-                       Fetch<'a, GameTimeNow>);
+                       WriteStorage<'a, Position>,
+                       Fetch<'a, GameClock>);
 
-    fn run(&mut self, (entities, utterances, now): Self::SystemData) {
+    fn run(&mut self, (entities, utterances, mut positions, clock): Self::SystemData) {
         use specs::Join;
-        (&*entities, &utterances).join().for_each(|(entity, utterance)| {
-            if utterance.dead_at < now.0 {
+        for (entity, utterance) in (&*entities, &utterances).join() {
+            if utterance.dead_at < clock.now {
                 entities.delete(entity).unwrap();
+                continue;
+            }
+            // Keep up with the speaker, while there is one.
+            let speaker_position = utterance.speaker
+                .filter(|&speaker| entities.is_alive(speaker))
+                .and_then(|speaker| positions.get(speaker).cloned());
+            if let Some(position) = speaker_position {
+                positions.insert(entity, position);
+            }
+        }
+    }
+}
+
+/// Keeps the player's avatar on the player's tile.
+pub struct AvatarSystem;
+
+impl<'a> System<'a> for AvatarSystem {
+    type SystemData = (ReadStorage<'a, Avatar>,
+                       WriteStorage<'a, Position>,
+                       Fetch<'a, PlayerPosition>);
+
+    fn run(&mut self, (avatars, mut positions, player): Self::SystemData) {
+        use specs::Join;
+        for (_, position) in (&avatars, &mut positions).join() {
+            *position = player.0;
+        }
+    }
+}
+
+/// Tuning for the boids rules. Distances are in tiles, speeds in tiles per
+/// tick.
+pub struct FlockingConfig {
+    pub view_radius: f32,
+    pub separation_radius: f32,
+    pub separation_weight: f32,
+    pub alignment_weight: f32,
+    pub cohesion_weight: f32,
+    pub wander_weight: f32,
+    pub damping: f32,
+    pub max_speed: f32,
+    /// Sheep closer than this to a threat run away from it.
+    pub flee_radius: f32,
+    pub flee_weight: f32,
+    pub flee_max_speed: f32,
+    /// How much of their speed calm sheep lose on the best grazing.
+    pub grazing_slowdown: f32,
+}
+
+impl Default for FlockingConfig {
+    fn default() -> FlockingConfig {
+        FlockingConfig {
+            view_radius: 6.0,
+            separation_radius: 1.5,
+            separation_weight: 0.004,
+            alignment_weight: 0.002,
+            cohesion_weight: 0.0008,
+            wander_weight: 0.004,
+            damping: 0.96,
+            max_speed: 0.05,
+            flee_radius: 5.0,
+            flee_weight: 0.012,
+            flee_max_speed: 0.12,
+            grazing_slowdown: 0.3,
+        }
+    }
+}
+
+pub struct FlockingSystem {
+    map: Arc<Mutex<TileMap>>,
+    rand: IsaacRng,
+    pub config: FlockingConfig,
+}
+
+impl FlockingSystem {
+    pub fn new(map: Arc<Mutex<TileMap>>, seed: u32) -> FlockingSystem {
+        FlockingSystem {
+            map,
+            rand: flock::seeded_rng(seed),
+            config: FlockingConfig::default(),
+        }
+    }
+}
+
+/// Sums the separation, alignment and cohesion rules for one sheep against
+/// the rest of the herd, returning the change in velocity.
+fn flock_steering(config: &FlockingConfig, position: &Position, herd: &[(Position, Heading)]) -> (f32, f32) {
+    let mut separation = (0.0, 0.0);
+    let mut alignment = (0.0, 0.0);
+    let mut centre = (0.0, 0.0);
+    let mut neighbours = 0;
+    for &(other, heading) in herd {
+        let dx = position.x - other.x;
+        let dy = position.y - other.y;
+        let distance_sq = dx * dx + dy * dy;
+        // Skip ourselves, and anything out of sight.
+        if distance_sq == 0.0 || distance_sq > config.view_radius * config.view_radius {
+            continue;
+        }
+        if distance_sq < config.separation_radius * config.separation_radius {
+            separation.0 += dx / distance_sq;
+            separation.1 += dy / distance_sq;
+        }
+        alignment.0 += heading.0.cos();
+        alignment.1 += heading.0.sin();
+        centre.0 += other.x;
+        centre.1 += other.y;
+        neighbours += 1;
+    }
+    if neighbours == 0 {
+        return (0.0, 0.0);
+    }
+    let count = neighbours as f32;
+    let cohesion = (centre.0 / count - position.x, centre.1 / count - position.y);
+    (
+        separation.0 * config.separation_weight +
+        (alignment.0 / count) * config.alignment_weight +
+        cohesion.0 * config.cohesion_weight,
+        separation.1 * config.separation_weight +
+        (alignment.1 / count) * config.alignment_weight +
+        cohesion.1 * config.cohesion_weight,
+    )
+}
+
+/// Points one sheep away from every threat inside the flee radius, stronger
+/// the closer the threat is. Returns the change in velocity and how urgent
+/// it is, from 0 (nothing nearby) to 1 (threat on top of us).
+fn flee_steering(config: &FlockingConfig, position: &Position, threats: &[Position]) -> ((f32, f32), f32) {
+    let mut flee = (0.0, 0.0);
+    let mut urgency: f32 = 0.0;
+    for threat in threats {
+        let dx = position.x - threat.x;
+        let dy = position.y - threat.y;
+        let distance = (dx * dx + dy * dy).sqrt();
+        if distance >= config.flee_radius {
+            continue;
+        }
+        let closeness = 1.0 - distance / config.flee_radius;
+        // A threat standing right on the sheep gives no direction; any will do.
+        let (dir_x, dir_y) = if distance > 0.0 { (dx / distance, dy / distance) } else { (1.0, 0.0) };
+        flee.0 += dir_x * closeness;
+        flee.1 += dir_y * closeness;
+        urgency = urgency.max(closeness);
+    }
+    ((flee.0 * config.flee_weight, flee.1 * config.flee_weight), urgency)
+}
+
+impl<'a> System<'a> for FlockingSystem {
+    type SystemData = (ReadStorage<'a, Sheep>,
+                       WriteStorage<'a, Position>,
+                       WriteStorage<'a, Velocity>,
+                       WriteStorage<'a, Heading>,
+                       ReadStorage<'a, Threat>,
+                       Fetch<'a, PlayerPosition>,
+                       Fetch<'a, GameClock>);
+
+    fn run(&mut self, (sheep, mut positions, mut velocities, mut headings, threat, player, clock): Self::SystemData) {
+        use specs::Join;
+        // Every sheep steers by where the herd was at the start of the tick,
+        // so the result doesn't depend on iteration order.
+        let herd: Vec<(Position, Heading)> = (&sheep, &positions, &headings).join()
+            .map(|(_, position, heading)| (*position, *heading))
+            .collect();
+        let mut threats = vec![player.0];
+        threats.extend((&threat, &positions).join().map(|(_, position)| *position));
+        let config = &self.config;
+        let rand = &mut self.rand;
+        // Sheep huddle closer together in the dark.
+        let huddle = if clock.day_phase() == DayPhase::Night { 2.0 } else { 1.0 };
+        let map = self.map.lock().unwrap();
+        (&sheep, &mut positions, &mut velocities, &mut headings).join().for_each(|(_, position, velocity, heading)| {
+            let steering = flock_steering(config, position, &herd);
+            let steering = (steering.0 * huddle, steering.1 * huddle);
+            let (flee, urgency) = flee_steering(config, position, &threats);
+            let wander = rand.gen_range(-PI, PI);
+            velocity.x = (velocity.x + steering.0 + flee.0 + wander.cos() * config.wander_weight) * config.damping;
+            velocity.y = (velocity.y + steering.1 + flee.1 + wander.sin() * config.wander_weight) * config.damping;
+            // Frightened sheep run faster than grazing ones, and calm ones
+            // linger where the grass is good. Rough ground slows them all.
+            let (movement_cost, grazing) = flock::point_tile_type(&map, position.x, position.y)
+                .map_or((1.0, 0.0), |tile_type| (tile_type.movement_cost, tile_type.grazing));
+            let max_speed = config.max_speed * (1.0 - config.grazing_slowdown * grazing * (1.0 - urgency));
+            let max_speed = (max_speed + (config.flee_max_speed - config.max_speed) * urgency) / movement_cost;
+            let speed = (velocity.x * velocity.x + velocity.y * velocity.y).sqrt();
+            if speed > max_speed {
+                velocity.x *= max_speed / speed;
+                velocity.y *= max_speed / speed;
+            }
+            if speed > config.max_speed * 0.1 {
+                heading.0 = velocity.y.atan2(velocity.x);
+            }
+
+            // Slide along obstacles where possible, otherwise bounce off.
+            let (x, y) = (position.x + velocity.x, position.y + velocity.y);
+            if flock::point_is_passable(&map, x, y) {
+                position.x = x;
+                position.y = y;
+            } else if flock::point_is_passable(&map, x, position.y) {
+                position.x = x;
+                velocity.y = 0.0;
+            } else if flock::point_is_passable(&map, position.x, y) {
+                position.y = y;
+                velocity.x = 0.0;
+            } else {
+                velocity.x = -velocity.x * 0.5;
+                velocity.y = -velocity.y * 0.5;
             }
         });
     }
 }
+
+/// The classic shepherd's whistles, given to the dog from the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DogCommand {
+    /// Circle the flock clockwise.
+    ComeBye,
+    /// Circle the flock anticlockwise.
+    AwayToMe,
+    /// Walk straight at the flock, pushing it away.
+    WalkUp,
+    /// Stop where you are.
+    LieDown,
+    /// Come back to heel.
+    ThatllDo,
+}
+
+impl fmt::Display for DogCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            DogCommand::ComeBye => "come-bye",
+            DogCommand::AwayToMe => "away to me",
+            DogCommand::WalkUp => "walk up",
+            DogCommand::LieDown => "lie down",
+            DogCommand::ThatllDo => "that'll do",
+        })
+    }
+}
+
+const DOG_SPEED: f32 = 0.15;
+const DOG_WALK_UP_SPEED: f32 = 0.06;
+/// How far the dog keeps outside the edge of the flock when circling it.
+const DOG_OUTRUN: f32 = 4.0;
+/// How much of the flock's circumference the dog aims ahead by, in radians.
+const DOG_CIRCLE_LEAD: f32 = 0.5;
+const DOG_SEARCH_TILES: usize = 4000;
+
+/// Moves a walker one tick along a path to the target. `step` is the tile
+/// it's currently heading for; a new one is picked from a path search each
+/// time it gets there, so walkers go round obstacles without keeping a whole
+/// path that the map or the target could invalidate.
+fn walk_towards(map: &TileMap, position: &mut Position, step: &mut Option<(usize, usize)>, target: Position, speed: f32, search_tiles: usize) {
+    // Rough ground slows everyone down.
+    let speed = speed / flock::point_tile_type(map, position.x, position.y).map_or(1.0, |tile_type| tile_type.movement_cost);
+    let reached_step = match *step {
+        Some((x, y)) => (x as f32 - position.x).abs() < speed && (y as f32 - position.y).abs() < speed,
+        None => true,
+    };
+    if reached_step {
+        let from = (position.x.round() as usize, position.y.round() as usize);
+        let to = (target.x.max(0.0).round() as usize, target.y.max(0.0).round() as usize);
+        *step = flock::next_step_towards(map, from, to, search_tiles);
+    }
+
+    if let Some((x, y)) = *step {
+        let dx = x as f32 - position.x;
+        let dy = y as f32 - position.y;
+        let distance = (dx * dx + dy * dy).sqrt();
+        if distance <= speed {
+            position.x = x as f32;
+            position.y = y as f32;
+        } else {
+            position.x += dx / distance * speed;
+            position.y += dy / distance * speed;
+        }
+    }
+}
+
+pub struct DogSystem {
+    map: Arc<Mutex<TileMap>>,
+}
+
+impl DogSystem {
+    pub fn new(map: Arc<Mutex<TileMap>>) -> DogSystem {
+        DogSystem {
+            map,
+        }
+    }
+}
+
+impl<'a> System<'a> for DogSystem {
+    type SystemData = (ReadStorage<'a, Sheep>,
+                       WriteStorage<'a, Dog>,
+                       WriteStorage<'a, Position>,
+                       Fetch<'a, PlayerPosition>,
+                       Fetch<'a, DogCommand>);
+
+    fn run(&mut self, (sheep, mut dogs, mut positions, player, command): Self::SystemData) {
+        use specs::Join;
+        let herd: Vec<Position> = (&sheep, &positions).join().map(|(_, position)| *position).collect();
+        let mut command = *command;
+        if herd.is_empty() && command != DogCommand::LieDown {
+            command = DogCommand::ThatllDo;
+        }
+        let centre = if herd.is_empty() {
+            player.0
+        } else {
+            Position {
+                x: herd.iter().map(|position| position.x).sum::<f32>() / herd.len() as f32,
+                y: herd.iter().map(|position| position.y).sum::<f32>() / herd.len() as f32,
+            }
+        };
+        let herd_radius = herd.iter()
+            .map(|position| ((position.x - centre.x).powi(2) + (position.y - centre.y).powi(2)).sqrt())
+            .fold(0.0, f32::max);
+
+        let map = self.map.lock().unwrap();
+        (&mut dogs, &mut positions).join().for_each(|(dog, position)| {
+            let (target, speed, arrive_radius) = match command {
+                DogCommand::LieDown => {
+                    dog.step = None;
+                    return;
+                }
+                DogCommand::ThatllDo => (player.0, DOG_SPEED, 1.5),
+                DogCommand::WalkUp => (centre, DOG_WALK_UP_SPEED, 0.0),
+                DogCommand::ComeBye | DogCommand::AwayToMe => {
+                    let lead = if command == DogCommand::ComeBye { DOG_CIRCLE_LEAD } else { -DOG_CIRCLE_LEAD };
+                    // Map y runs downwards, so a growing angle goes clockwise on screen.
+                    let angle = (position.y - centre.y).atan2(position.x - centre.x) + lead;
+                    let radius = herd_radius + DOG_OUTRUN;
+                    (Position { x: centre.x + angle.cos() * radius, y: centre.y + angle.sin() * radius }, DOG_SPEED, 0.0)
+                }
+            };
+
+            let distance_to_target = ((target.x - position.x).powi(2) + (target.y - position.y).powi(2)).sqrt();
+            if distance_to_target < arrive_radius {
+                dog.step = None;
+                return;
+            }
+
+            walk_towards(&map, position, &mut dog.step, target, speed, DOG_SEARCH_TILES);
+        });
+    }
+}
+
+/// Which map tiles are inside pens, by index, worked out once each time a
+/// fence or gate changes.
+#[derive(Default)]
+pub struct Enclosure {
+    pub tiles: Vec<bool>,
+    revision: Option<u32>,
+}
+
+/// Keeps the `Enclosure` up to date with the map.
+pub struct EnclosureSystem {
+    map: Arc<Mutex<TileMap>>,
+}
+
+impl EnclosureSystem {
+    pub fn new(map: Arc<Mutex<TileMap>>) -> EnclosureSystem {
+        EnclosureSystem {
+            map,
+        }
+    }
+}
+
+impl<'a> System<'a> for EnclosureSystem {
+    type SystemData = FetchMut<'a, Enclosure>;
+
+    fn run(&mut self, mut enclosure: Self::SystemData) {
+        let map = self.map.lock().unwrap();
+        // Flood filling the whole map is slow, so only redo it when a fence
+        // or gate has changed.
+        if enclosure.revision != Some(map.revision) {
+            *enclosure = Enclosure {
+                tiles: flock::enclosed_tiles(&map),
+                revision: Some(map.revision),
+            };
+        }
+    }
+}
+
+/// How many sheep are inside pens right now, out of the whole flock.
+#[derive(Default)]
+pub struct PenCount {
+    pub penned: usize,
+    pub total: usize,
+}
+
+/// Counts the sheep in pens, and keeps the log up to date with the pens and
+/// the time of day.
+pub struct PenSystem {
+    map: Arc<Mutex<TileMap>>,
+    phase: Option<DayPhase>,
+}
+
+impl PenSystem {
+    pub fn new(map: Arc<Mutex<TileMap>>) -> PenSystem {
+        PenSystem {
+            map,
+            phase: None,
+        }
+    }
+}
+
+impl<'a> System<'a> for PenSystem {
+    type SystemData = (ReadStorage<'a, Sheep>,
+                       ReadStorage<'a, Position>,
+                       Fetch<'a, GameClock>,
+                       Fetch<'a, Enclosure>,
+                       FetchMut<'a, PenCount>,
+                       FetchMut<'a, EventLog>);
+
+    fn run(&mut self, (sheep, positions, clock, enclosure, mut pen_count, mut log): Self::SystemData) {
+        use specs::Join;
+        let map = self.map.lock().unwrap();
+        let enclosed = &enclosure.tiles;
+        let mut count = PenCount::default();
+        (&sheep, &positions).join().for_each(|(_, position)| {
+            count.total += 1;
+            let index = position.y.round() as usize * map.width + position.x.round() as usize;
+            if enclosed.get(index) == Some(&true) {
+                count.penned += 1;
+            }
+        });
+        // Sheep going missing is logged by whatever took them, so only
+        // report comings and goings while the flock is all there.
+        if self.phase.is_some() && count.total == pen_count.total && count.penned != pen_count.penned {
+            let message = match count.penned as isize - pen_count.penned as isize {
+                1 => String::from("A sheep was penned"),
+                -1 => String::from("A sheep got out of the pen"),
+                change if change > 0 => format!("{} sheep were penned", change),
+                change => format!("{} sheep got out of the pen", -change),
+            };
+            log.push(clock.now, format!("{} ({}/{})", message, count.penned, count.total));
+        }
+        *pen_count = count;
+
+        // The working day ends at nightfall.
+        let phase = clock.day_phase();
+        if self.phase.is_some_and(|previous| previous != phase) {
+            log.push(clock.now, match phase {
+                DayPhase::Dawn => format!("Day {} dawns", clock.day_number()),
+                DayPhase::Noon => String::from("The sun is high"),
+                DayPhase::Dusk => String::from("Dusk is falling"),
+                DayPhase::Night => String::from("Night falls"),
+            });
+        }
+        if phase == DayPhase::Night && self.phase == Some(DayPhase::Dusk) {
+            log.push(clock.now, format!("End of day {}: {}/{} sheep penned", clock.day_number(), pen_count.penned, pen_count.total));
+            if pen_count.total > 0 && pen_count.penned == pen_count.total {
+                log.push(clock.now, "The whole flock is home. You win!");
+            }
+        }
+        self.phase = Some(phase);
+    }
+}
+
+/// Chance each tick of the weather turning, so it changes a few times a day.
+const WEATHER_CHANGE_CHANCE: f32 = 1.0 / (60.0 * 90.0);
+
+pub struct WeatherSystem {
+    rand: IsaacRng,
+}
+
+impl<'a> System<'a> for WeatherSystem {
+    type SystemData = (Fetch<'a, GameClock>,
+                       FetchMut<'a, Weather>,
+                       FetchMut<'a, EventLog>);
+
+    fn run(&mut self, (clock, mut weather, mut log): Self::SystemData) {
+        if self.rand.next_f32() >= WEATHER_CHANGE_CHANCE {
+            return;
+        }
+        let (next, message) = match *weather {
+            Weather::Clear => (Weather::Cloudy, "Clouds are gathering"),
+            Weather::Cloudy => match self.rand.gen_range(0, 3) {
+                0 => (Weather::Clear, "The clouds are clearing"),
+                1 => (Weather::Rain, "It's started to rain"),
+                _ => (Weather::Fog, "A fog is coming down"),
+            },
+            Weather::Rain => (Weather::Cloudy, "The rain has stopped"),
+            Weather::Fog => (Weather::Clear, "The fog has lifted"),
+        };
+        *weather = next;
+        log.push(clock.now, message);
+    }
+}
+
+/// Most wolves that can be out at once.
+const WOLF_PACK_SIZE: usize = 2;
+/// Chance each tick of a wolf coming out at night, while there's room in the
+/// pack.
+const WOLF_SPAWN_CHANCE: f32 = 1.0 / (60.0 * 90.0);
+/// Wolves only come out of dens at least this far from the player, so they
+/// don't appear on screen.
+const WOLF_SPAWN_DISTANCE: f32 = 40.0;
+const WOLF_SPEED: f32 = 0.08;
+const WOLF_FLEE_SPEED: f32 = 0.16;
+/// A wolf this close to the player or a dog gives up and runs.
+const WOLF_FEAR_RADIUS: f32 = 6.0;
+const WOLF_FLEE_TICKS: u32 = 60 * 5;
+/// Sheep within this distance of each other count as company.
+const WOLF_ISOLATION_RADIUS: f32 = 4.0;
+const WOLF_KILL_RADIUS: f32 = 0.8;
+const WOLF_SEARCH_TILES: usize = 2000;
+
+pub struct WolfSystem {
+    map: Arc<Mutex<TileMap>>,
+    dens: Vec<(usize, usize)>,
+    revision: Option<u32>,
+    rand: IsaacRng,
+}
+
+impl WolfSystem {
+    pub fn new(map: Arc<Mutex<TileMap>>, seed: u32) -> WolfSystem {
+        WolfSystem {
+            map,
+            dens: Vec::new(),
+            revision: None,
+            rand: flock::seeded_rng(seed),
+        }
+    }
+}
+
+fn distance(a: &Position, b: &Position) -> f32 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+impl<'a> System<'a> for WolfSystem {
+    type SystemData = (Entities<'a>,
+                       ReadStorage<'a, Sheep>,
+                       ReadStorage<'a, Dog>,
+                       WriteStorage<'a, Wolf>,
+                       WriteStorage<'a, Threat>,
+                       WriteStorage<'a, Position>,
+                       Fetch<'a, PlayerPosition>,
+                       Fetch<'a, GameClock>,
+                       FetchMut<'a, EventLog>);
+
+    fn run(&mut self, (entities, sheep, dogs, mut wolves, mut threats, mut positions, player, clock, mut log): Self::SystemData) {
+        use specs::Join;
+        let map = self.map.lock().unwrap();
+        if self.revision != Some(map.revision) {
+            self.dens = flock::wolf_dens(&map);
+            self.revision = Some(map.revision);
+        }
+        let mut guardians = vec![player.0];
+        guardians.extend((&dogs, &positions).join().map(|(_, position)| *position));
+        let herd: Vec<_> = (&*entities, &sheep, &positions).join()
+            .map(|(entity, _, position)| (entity, *position))
+            .collect();
+
+        let night = clock.day_phase() == DayPhase::Night;
+        let pack_size = (&wolves).join().count();
+        if night && pack_size < WOLF_PACK_SIZE && !self.dens.is_empty() && self.rand.next_f32() < WOLF_SPAWN_CHANCE {
+            let (x, y) = self.dens[self.rand.gen_range(0, self.dens.len())];
+            let den = Position { x: x as f32, y: y as f32 };
+            if guardians.iter().all(|guardian| distance(guardian, &den) > WOLF_SPAWN_DISTANCE) {
+                let wolf = entities.create();
+                wolves.insert(wolf, Wolf { state: WolfState::Hunting, step: None });
+                threats.insert(wolf, Threat);
+                positions.insert(wolf, den);
+            }
+        }
+
+        let mut eaten = Vec::new();
+        for (wolf_entity, wolf, position) in (&*entities, &mut wolves, &mut positions).join() {
+            let nearest_guardian = guardians.iter()
+                .min_by(|a, b| distance(a, position).partial_cmp(&distance(b, position)).unwrap())
+                .cloned()
+                .unwrap_or(player.0);
+            if wolf.state == WolfState::Hunting && distance(&nearest_guardian, position) < WOLF_FEAR_RADIUS {
+                wolf.state = WolfState::Fleeing { ticks: WOLF_FLEE_TICKS };
+                log.push(clock.now, "A wolf was chased off");
+            } else if wolf.state == WolfState::Hunting && !night {
+                // Wolves slink away once it gets light.
+                wolf.state = WolfState::Fleeing { ticks: WOLF_FLEE_TICKS };
+            }
+
+            match wolf.state {
+                WolfState::Fleeing { ticks: 0 } => {
+                    entities.delete(wolf_entity).unwrap();
+                }
+                WolfState::Fleeing { ticks } => {
+                    wolf.state = WolfState::Fleeing { ticks: ticks - 1 };
+                    let away = Position {
+                        x: position.x + (position.x - nearest_guardian.x) * 4.0,
+                        y: position.y + (position.y - nearest_guardian.y) * 4.0,
+                    };
+                    walk_towards(&map, position, &mut wolf.step, away, WOLF_FLEE_SPEED, WOLF_SEARCH_TILES);
+                }
+                WolfState::Hunting => {
+                    // Go for the sheep with the least company, nearest first.
+                    let prey = herd.iter()
+                        .filter(|&&(entity, _)| !eaten.contains(&entity))
+                        .map(|&(entity, sheep_position)| {
+                            let company = herd.iter()
+                                .filter(|&&(_, other)| distance(&other, &sheep_position) < WOLF_ISOLATION_RADIUS)
+                                .count();
+                            (entity, sheep_position, company, distance(&sheep_position, position))
+                        })
+                        .min_by(|a, b| (a.2, a.3).partial_cmp(&(b.2, b.3)).unwrap());
+                    if let Some((sheep_entity, sheep_position, _, sheep_distance)) = prey {
+                        if sheep_distance < WOLF_KILL_RADIUS {
+                            entities.delete(sheep_entity).unwrap();
+                            eaten.push(sheep_entity);
+                            log.push(clock.now, "A wolf took a sheep");
+                            wolf.state = WolfState::Fleeing { ticks: WOLF_FLEE_TICKS };
+                        } else {
+                            walk_towards(&map, position, &mut wolf.step, sheep_position, WOLF_SPEED, WOLF_SEARCH_TILES);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Biggest the minimap gets, in screen cells.
+const MINIMAP_MAX_WIDTH: usize = 40;
+const MINIMAP_MAX_HEIGHT: usize = 20;
+
+/// The whole map shrunk into a few screen cells, each showing the most
+/// common style of tile in its block. Blocks are twice as tall as they are
+/// wide, as screen cells are.
+#[derive(Default)]
+pub struct Minimap {
+    pub width: usize,
+    pub height: usize,
+    /// Map tiles across one cell; a cell is twice this many tiles tall.
+    pub block: usize,
+    pub types: Arc<TileTypes>,
+    pub styles: Vec<TileStyle>,
+    /// Cells holding any penned-in ground.
+    pub pens: Vec<bool>,
+    revision: Option<u32>,
+}
+
+impl Minimap {
+    /// The cell a map point falls in, if it's on the map.
+    pub fn cell_at(&self, x: f32, y: f32) -> Option<(usize, usize)> {
+        if self.block == 0 || x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let cell = (x.round() as usize / self.block, y.round() as usize / (self.block * 2));
+        if cell.0 >= self.width || cell.1 >= self.height {
+            return None;
+        }
+        Some(cell)
+    }
+}
+
+/// Rebuilds the minimap whenever the map changes.
+pub struct MinimapSystem {
+    map: Arc<Mutex<TileMap>>,
+}
+
+impl MinimapSystem {
+    pub fn new(map: Arc<Mutex<TileMap>>) -> MinimapSystem {
+        MinimapSystem {
+            map,
+        }
+    }
+}
+
+impl<'a> System<'a> for MinimapSystem {
+    type SystemData = (Fetch<'a, Enclosure>,
+                       FetchMut<'a, Minimap>);
+
+    fn run(&mut self, (enclosure, mut minimap): Self::SystemData) {
+        let map = self.map.lock().unwrap();
+        if minimap.revision == Some(map.revision) {
+            return;
+        }
+        let block = cmp::max(
+            map.width.div_ceil(MINIMAP_MAX_WIDTH),
+            map.height.div_ceil(MINIMAP_MAX_HEIGHT * 2),
+        );
+        let width = map.width.div_ceil(block);
+        let height = map.height.div_ceil(block * 2);
+        let enclosed = &enclosure.tiles;
+        let mut styles = Vec::with_capacity(width * height);
+        let mut pens = Vec::with_capacity(width * height);
+        for cell_y in 0..height {
+            for cell_x in 0..width {
+                let mut penned = false;
+                for y in cell_y * block * 2..cmp::min((cell_y + 1) * block * 2, map.height) {
+                    for x in cell_x * block..cmp::min((cell_x + 1) * block, map.width) {
+                        penned |= enclosed[y * map.width + x];
+                    }
+                }
+                styles.push(map.majority_style(cell_x * block, cell_y * block * 2, block, block * 2).unwrap());
+                pens.push(penned);
+            }
+        }
+        *minimap = Minimap {
+            width,
+            height,
+            block,
+            types: map.types.clone(),
+            styles,
+            pens,
+            revision: Some(map.revision),
+        };
+    }
+}
+
+/// Offsets from the world seed for each system's randomness, so they
+/// don't all draw the same numbers. Map generation and spawning use 0 and 1.
+const FLOCKING_SEED: u32 = 2;
+const WOLF_SEED: u32 = 3;
+const WEATHER_SEED: u32 = 4;
+
+/// Asks the simulation to start its randomness over from a world seed
+/// before the next tick, as when a game is loaded.
+#[derive(Default)]
+pub struct Reseed(pub Option<u32>);
+
+/// Every system in the game, run in order once per tick.
+pub struct Simulation {
+    avatar_system: AvatarSystem,
+    utterance_system: UtteranceSystem,
+    flocking_system: FlockingSystem,
+    dog_system: DogSystem,
+    wolf_system: WolfSystem,
+    enclosure_system: EnclosureSystem,
+    pen_system: PenSystem,
+    weather_system: WeatherSystem,
+    minimap_system: MinimapSystem,
+}
+
+impl Simulation {
+    /// Randomness is seeded from the map's seed, so the same world and the
+    /// same input always play out the same way.
+    pub fn new(map: Arc<Mutex<TileMap>>) -> Simulation {
+        let seed = map.lock().unwrap().seed;
+        Simulation {
+            avatar_system: AvatarSystem,
+            utterance_system: UtteranceSystem,
+            flocking_system: FlockingSystem::new(map.clone(), seed.wrapping_add(FLOCKING_SEED)),
+            dog_system: DogSystem::new(map.clone()),
+            wolf_system: WolfSystem::new(map.clone(), seed.wrapping_add(WOLF_SEED)),
+            enclosure_system: EnclosureSystem::new(map.clone()),
+            pen_system: PenSystem::new(map.clone()),
+            weather_system: WeatherSystem { rand: flock::seeded_rng(seed.wrapping_add(WEATHER_SEED)) },
+            minimap_system: MinimapSystem::new(map),
+        }
+    }
+
+    /// Starts every system's randomness over from a world seed.
+    fn reseed(&mut self, seed: u32) {
+        self.flocking_system.rand = flock::seeded_rng(seed.wrapping_add(FLOCKING_SEED));
+        self.wolf_system.rand = flock::seeded_rng(seed.wrapping_add(WOLF_SEED));
+        self.weather_system.rand = flock::seeded_rng(seed.wrapping_add(WEATHER_SEED));
+    }
+
+    /// Moves the game clock on by one tick and runs every system. The map
+    /// must not be locked by the caller.
+    pub fn tick(&mut self, world: &mut World) {
+        let reseed = world.write_resource::<Reseed>().0.take();
+        if let Some(seed) = reseed {
+            self.reseed(seed);
+        }
+        world.write_resource::<GameClock>().tick();
+        self.avatar_system.run_now(&world.res);
+        self.flocking_system.run_now(&world.res);
+        self.dog_system.run_now(&world.res);
+        self.wolf_system.run_now(&world.res);
+        self.enclosure_system.run_now(&world.res);
+        self.pen_system.run_now(&world.res);
+        self.weather_system.run_now(&world.res);
+        self.minimap_system.run_now(&world.res);
+        // Last, so speech follows wherever its speaker ended up.
+        self.utterance_system.run_now(&world.res);
+        world.maintain();
+    }
+
+    /// Brings pens, the pen count and the minimap up to date with the map
+    /// without moving the clock, so fences built while paused show at once.
+    /// The map must not be locked by the caller.
+    pub fn refresh(&mut self, world: &mut World) {
+        self.enclosure_system.run_now(&world.res);
+        self.pen_system.run_now(&world.res);
+        self.minimap_system.run_now(&world.res);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use specs::Join;
+
+    use time::Duration;
+
+    use ecs_entities::{Position, Sheep};
+
+    use tile::{Tile, TileMap};
+
+    use tile_types::TileTypes;
+
+    use flock;
+
+    use super::{new_world, DayPhase, GameClock, Minimap, PenCount, PlayerPosition, Simulation};
+    use super::{DAY_LENGTH, MAX_CATCH_UP_TICKS, TICK_MICROSECONDS};
+
+    /// Every entity's position after running a fresh game for a while.
+    fn positions_after(seed: u32, ticks: usize) -> Vec<(f32, f32)> {
+        let map = flock::generate_tilemap(64, 48, seed, Arc::new(TileTypes::default()));
+        let mut world = new_world();
+        world.add_resource(PlayerPosition(Position { x: 32.0, y: 24.0 }));
+        flock::spawn_flock(&mut world, &map, 32, 24, 10);
+        flock::spawn_dog(&mut world, &map, 32, 24);
+        let mut simulation = Simulation::new(Arc::new(Mutex::new(map)));
+        for _ in 0..ticks {
+            simulation.tick(&mut world);
+        }
+        let positions = world.read::<Position>();
+        (&positions).join().map(|position| (position.x, position.y)).collect()
+    }
+
+    #[test]
+    fn same_seed_plays_out_the_same() {
+        let first = positions_after(7, 300);
+        assert_eq!(first.len(), 11);
+        assert_eq!(first, positions_after(7, 300));
+    }
+
+    #[test]
+    fn pens_built_without_ticking_are_counted() {
+        let types = Arc::new(TileTypes::default());
+        let mut map = TileMap::new(8, 6, 0, types.clone());
+        map.fill_tiles(|x, y| Tile { x, y, style: types.style("grass-plain"), rand_offset: 0.0 });
+        let map = Arc::new(Mutex::new(map));
+        let mut world = new_world();
+        world.add_resource(PlayerPosition(Position { x: 0.0, y: 0.0 }));
+        world.create_entity().with(Sheep).with(Position { x: 3.0, y: 3.0 }).build();
+        let mut simulation = Simulation::new(map.clone());
+        simulation.refresh(&mut world);
+        assert_eq!(world.read_resource::<PenCount>().penned, 0);
+
+        {
+            let mut map = map.lock().unwrap();
+            for i in 2..5 {
+                for &(x, y) in &[(i, 2), (i, 4), (2, i), (4, i)] {
+                    map.set_tile_style(x, y, types.style("fence"));
+                }
+            }
+        }
+        simulation.refresh(&mut world);
+        assert_eq!(world.read_resource::<PenCount>().penned, 1);
+        assert!(world.read_resource::<Minimap>().pens.contains(&true));
+        assert_eq!(world.read_resource::<GameClock>().now, Duration::zero());
+    }
+
+    fn ticks(count: i64) -> Duration {
+        Duration::microseconds(count * TICK_MICROSECONDS)
+    }
+
+    #[test]
+    fn paused_clock_runs_no_ticks() {
+        let mut clock = GameClock::default();
+        clock.toggle_pause();
+        assert_eq!(clock.advance(ticks(5)), 0);
+        // Time passed while paused isn't owed afterwards.
+        clock.toggle_pause();
+        assert_eq!(clock.advance(Duration::zero()), 0);
+    }
+
+    #[test]
+    fn time_scale_multiplies_ticks() {
+        let mut clock = GameClock::default();
+        assert_eq!(clock.advance(ticks(3)), 3);
+        clock.speed_up();
+        assert_eq!(clock.time_scale(), 2.0);
+        assert_eq!(clock.advance(ticks(3)), 6);
+        clock.slow_down();
+        clock.slow_down();
+        assert_eq!(clock.advance(ticks(4)), 2);
+    }
+
+    #[test]
+    fn part_ticks_carry_over() {
+        let mut clock = GameClock::default();
+        let three_quarters = Duration::microseconds(TICK_MICROSECONDS * 3 / 4);
+        assert_eq!(clock.advance(three_quarters), 0);
+        assert_eq!(clock.advance(three_quarters), 1);
+        assert_eq!(clock.advance(three_quarters), 1);
+    }
+
+    #[test]
+    fn catching_up_is_capped() {
+        let mut clock = GameClock::default();
+        assert_eq!(clock.advance(Duration::seconds(10)), MAX_CATCH_UP_TICKS as u32);
+    }
+
+    #[test]
+    fn timestamps_start_at_dawn_on_day_one() {
+        assert_eq!(GameClock::timestamp(Duration::zero()), "day 1 06:00");
+        assert_eq!(GameClock::timestamp(Duration::seconds(DAY_LENGTH / 4)), "day 1 12:00");
+        assert_eq!(GameClock::timestamp(Duration::seconds(DAY_LENGTH * 3 / 4)), "day 2 00:00");
+    }
+
+    #[test]
+    fn days_go_through_their_phases() {
+        let mut clock = GameClock::default();
+        let phase_at = |clock: &mut GameClock, fraction: f32| {
+            clock.now = Duration::milliseconds((DAY_LENGTH as f32 * 1000.0 * fraction) as i64);
+            clock.day_phase()
+        };
+        assert_eq!(phase_at(&mut clock, 0.0), DayPhase::Dawn);
+        assert_eq!(phase_at(&mut clock, 0.3), DayPhase::Noon);
+        assert_eq!(phase_at(&mut clock, 0.7), DayPhase::Dusk);
+        assert_eq!(phase_at(&mut clock, 0.9), DayPhase::Night);
+        assert_eq!(phase_at(&mut clock, 1.0), DayPhase::Dawn);
+        assert_eq!(clock.day_number(), 2);
+    }
+}
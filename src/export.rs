@@ -0,0 +1,180 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+use png;
+
+use tile::TileMap;
+
+use cell_buffer::{Cell, CellBuffer};
+
+/// Where the view being shown is written when exported from the game.
+pub const VIEW_HTML_PATH: &str = "flock-view.html";
+pub const VIEW_ANS_PATH: &str = "flock-view.ans";
+
+#[derive(Debug)]
+pub struct ExportError(String);
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(error: io::Error) -> ExportError {
+        ExportError(error.to_string())
+    }
+}
+
+impl From<png::EncodingError> for ExportError {
+    fn from(error: png::EncodingError) -> ExportError {
+        ExportError(error.to_string())
+    }
+}
+
+/// Most pixels an exported image can have, so a big `--scale` fails up
+/// front rather than eating all the memory.
+const MAX_PIXELS: usize = 100_000_000;
+
+/// Writes an image `scale` pixels square per tile, taking each tile's
+/// pixel data from `pixel`.
+fn write_png<F>(path: &str, width: usize, height: usize, scale: usize, colour: png::ColorType, pixel: F) -> Result<(), ExportError>
+where
+    F: Fn(usize, usize) -> Vec<u8>,
+{
+    let fits = match (width.checked_mul(scale), height.checked_mul(scale)) {
+        (Some(image_width), Some(image_height)) => image_width.checked_mul(image_height).is_some_and(|pixels| pixels <= MAX_PIXELS),
+        _ => false,
+    };
+    if !fits {
+        return Err(ExportError(format!("{}x{} tiles at scale {} would be over {} pixels", width, height, scale, MAX_PIXELS)));
+    }
+    let mut data = Vec::new();
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            data.extend(pixel(x / scale, y / scale));
+        }
+    }
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(colour);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(())
+}
+
+/// Saves the map as a PNG, each tile a block of its terminal background
+/// colour.
+pub fn write_map_png(map: &TileMap, path: &str, scale: usize) -> Result<(), ExportError> {
+    write_png(path, map.width, map.height, scale, png::ColorType::Rgb, |x, y| {
+        map.tile_type(&map.tiles[y * map.width + x]).colour_bg.to_vec()
+    })
+}
+
+/// Saves tile heights as a greyscale PNG, from black at -1 to white at 1.
+/// Anything lower is deep sea and anything higher is high rock, so those
+/// are left at the ends of the scale.
+pub fn write_heightmap_png(heights: &[f32], width: usize, height: usize, path: &str, scale: usize) -> Result<(), ExportError> {
+    write_png(path, width, height, scale, png::ColorType::Grayscale, |x, y| {
+        vec![((heights[y * width + x].clamp(-1.0, 1.0) + 1.0) / 2.0 * 255.0).round() as u8]
+    })
+}
+
+fn hex(colour: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", colour[0], colour[1], colour[2])
+}
+
+/// Cells in a row grouped into runs of the same colours.
+fn colour_runs(row: &[Cell]) -> Vec<(&Cell, String)> {
+    let mut runs: Vec<(&Cell, String)> = Vec::new();
+    for cell in row {
+        match runs.last_mut() {
+            Some(&mut (first, ref mut text)) if first.fg == cell.fg && first.bg == cell.bg => text.push(cell.glyph),
+            _ => runs.push((cell, cell.glyph.to_string())),
+        }
+    }
+    runs
+}
+
+/// Saves cells as a standalone web page, each run of colour a styled span.
+/// Plain, it's just the glyphs, with no colour at all.
+pub fn write_html(buffer: &CellBuffer, title: &str, path: &str, plain: bool) -> Result<(), ExportError> {
+    let escape = |text: &str| text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n", title)?;
+    if plain {
+        writeln!(file, "<style>pre {{ font-family: monospace; line-height: 1; }}</style>")?;
+    } else {
+        writeln!(file, "<style>body {{ background: #000; }} pre {{ font-family: monospace; line-height: 1; }}</style>")?;
+    }
+    writeln!(file, "</head>\n<body>\n<pre>")?;
+    for row in buffer.rows() {
+        if plain {
+            write!(file, "{}", escape(&row.iter().map(|cell| cell.glyph).collect::<String>()))?;
+        } else {
+            for (cell, text) in colour_runs(row) {
+                write!(file, "<span style=\"color: {}; background: {}\">{}</span>", hex(cell.fg), hex(cell.bg), escape(&text))?;
+            }
+        }
+        writeln!(file)?;
+    }
+    writeln!(file, "</pre>\n</body>\n</html>")?;
+    Ok(())
+}
+
+/// Saves cells as ANSI art, with 24-bit colour escapes for each run of
+/// colour, for `cat`ing to a terminal. Plain, it's just the glyphs, with
+/// no escapes at all.
+pub fn write_ans(buffer: &CellBuffer, path: &str, plain: bool) -> Result<(), ExportError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for row in buffer.rows() {
+        if plain {
+            writeln!(file, "{}", row.iter().map(|cell| cell.glyph).collect::<String>())?;
+            continue;
+        }
+        for (cell, text) in colour_runs(row) {
+            write!(
+                file,
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m{}",
+                cell.fg[0], cell.fg[1], cell.fg[2], cell.bg[0], cell.bg[1], cell.bg[2], text
+            )?;
+        }
+        writeln!(file, "\x1b[0m")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use cell_buffer::CellBuffer;
+
+    use super::{write_ans, write_heightmap_png, write_html};
+
+    #[test]
+    fn refuses_images_too_big_to_make() {
+        let path = env::temp_dir().join("flock-too-big.png");
+        let path = path.to_str().unwrap();
+        let heights = [0.0; 4];
+        let error = write_heightmap_png(&heights, 2, 2, path, 10_000).err().unwrap();
+        assert_eq!(error.to_string(), "2x2 tiles at scale 10000 would be over 100000000 pixels");
+        assert!(write_heightmap_png(&heights, 2, 2, path, usize::MAX / 2).is_err());
+    }
+
+    #[test]
+    fn plain_exports_have_no_colour() {
+        let mut buffer = CellBuffer::new(4, 2);
+        buffer.put_str(0, 0, "a<b", [255, 0, 0], [0, 0, 255]);
+        let ans_path = env::temp_dir().join("flock-plain.ans");
+        let html_path = env::temp_dir().join("flock-plain.html");
+        write_ans(&buffer, ans_path.to_str().unwrap(), true).unwrap();
+        write_html(&buffer, "plain", html_path.to_str().unwrap(), true).unwrap();
+        assert_eq!(fs::read_to_string(&ans_path).unwrap(), "a<b \n    \n");
+        let html = fs::read_to_string(&html_path).unwrap();
+        assert!(html.contains("<pre>\na&lt;b \n    \n</pre>"));
+        assert!(!html.contains("color") && !html.contains("background"));
+    }
+}
@@ -1,14 +1,41 @@
-use rand::{thread_rng, Rng};
+use std::cmp;
+use std::f32::consts::PI;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+use rand::{IsaacRng, Rng, SeedableRng};
 
 use noise::{Add, NoiseModule, Perlin, ScaleBias, ScalePoint, Seedable};
 
 use tile::Tile;
 use tile::TileMap;
-use tile::TileStyle;
+
+use tile_types::{TileType, TileTypes};
+
+use specs::{Entity, World, Join};
+
+use time::Duration;
+
+use ecs_entities::{Position, Velocity, Heading, Sheep, Dog, Threat, Wolf, Avatar, Utterance};
+
+use ecs_systems::{GameClock, PlayerPosition, EventLog};
+
+use lighting::Lighting;
+
+use player::Direction;
+
+/// Tiles around the player that a description of the terrain covers.
+const DESCRIBE_TERRAIN_RADIUS: f32 = 10.0;
+/// How far away animals are worth mentioning, light permitting.
+const DESCRIBE_ANIMALS_RADIUS: f32 = 30.0;
+/// Most kinds of terrain a description lists, commonest first.
+const DESCRIBE_TERRAIN_TYPES: usize = 3;
 
 type NoiseScaled = ScaleBias<ScalePoint<Perlin, f32>, f32>;
-fn get_noise_map() -> Add<Perlin, Add<NoiseScaled, Add<NoiseScaled, Add<NoiseScaled, NoiseScaled>>>> {
-    let perlin_1 = Perlin::new().set_seed(thread_rng().next_u32() as usize);
+type NoiseMap = Add<Perlin, Add<NoiseScaled, Add<NoiseScaled, Add<NoiseScaled, NoiseScaled>>>>;
+
+fn get_noise_map(seed: u32) -> NoiseMap {
+    let perlin_1 = Perlin::new().set_seed(seed as usize);
     let perlin_2 = ScaleBias::new(ScalePoint::new(perlin_1).set_x_scale(2.0).set_y_scale(2.0)).set_scale(0.5);
     let perlin_3 = ScaleBias::new(ScalePoint::new(perlin_1).set_x_scale(4.0).set_y_scale(4.0)).set_scale(0.25);
     let perlin_4 = ScaleBias::new(ScalePoint::new(perlin_1).set_x_scale(8.0).set_y_scale(8.0)).set_scale(0.125);
@@ -16,62 +43,105 @@ fn get_noise_map() -> Add<Perlin, Add<NoiseScaled, Add<NoiseScaled, Add<NoiseSca
     Add::new(perlin_1, Add::new(perlin_2, Add::new(perlin_3, Add::new(perlin_4, perlin_5))))
 }
 
-pub fn generate_tilemap(width: usize, height: usize) -> TileMap {
-    let mut map = TileMap::new(width, height);
+/// Returns a deterministic RNG for the given world seed. Everything random
+/// about a generated map is drawn from one of these, so the same seed always
+/// produces the same map.
+pub fn seeded_rng(seed: u32) -> IsaacRng {
+    IsaacRng::from_seed(&[seed])
+}
 
-    let heightmap = get_noise_map();
-    let farmland = get_noise_map();
-    let vegetation = get_noise_map();
+/// Noise sample coordinates for a tile.
+fn noise_coord(x: usize, y: usize) -> [f32; 2] {
+    [x as f32 / 64.0, y as f32 / 64.0]
+}
+
+/// How high the land is at a tile, sinking towards the edges of the map so
+/// it's an island. Above 0.55 is bare ground or rock, below -0.7 is water.
+fn tile_height(heightmap: &NoiseMap, x: usize, y: usize, width: usize, height: usize) -> f32 {
+    let distance_from_centre = (
+        ((x as isize - (width as isize / 2)) as f32).abs().powi(2) +
+        ((y as isize - (height as isize / 2)) as f32).abs().powi(2)
+    ).sqrt();
+    heightmap.get(noise_coord(x, y)) - ((distance_from_centre / (width / 2) as f32).powi(4) * 2.0)
+}
+
+/// The land height of every tile of the map `generate_tilemap` makes from
+/// the same size and seed, row by row.
+pub fn generate_heightmap(width: usize, height: usize, seed: u32) -> Vec<f32> {
+    let heightmap = get_noise_map(seeded_rng(seed).next_u32());
+    let mut heights = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            heights.push(tile_height(&heightmap, x, y, width, height));
+        }
+    }
+    heights
+}
+
+pub fn generate_tilemap(width: usize, height: usize, seed: u32, types: Arc<TileTypes>) -> TileMap {
+    let rock_high = types.style("rock-high");
+    let rock_low = types.style("rock-low");
+    let dirt = types.style("dirt");
+    let dirt_farmed = types.style("dirt-farmed");
+    let tree = types.style("tree");
+    let grass_plain = types.style("grass-plain");
+    let grass_coastal = types.style("grass-coastal");
+    let sand = types.style("sand");
+    let water_shallow = types.style("water-shallow");
+    let water_deep = types.style("water-deep");
+    let mut map = TileMap::new(width, height, seed, types);
+
+    // Derive a seed for each noise layer, then use the same stream for the
+    // per-tile randomness. Order matters here: don't reorder these draws.
+    let mut rand = seeded_rng(seed);
+    let heightmap = get_noise_map(rand.next_u32());
+    let farmland = get_noise_map(rand.next_u32());
+    let vegetation = get_noise_map(rand.next_u32());
 
     map.fill_tiles(move |x, y| {
-        let mut rand = thread_rng();
-        let coord = [x as f32 / 64.0, y as f32 / 64.0];
-        let distance_from_centre = (
-            ((x as isize - (width as isize / 2)) as f32).abs().powi(2) +
-            ((y as isize - (height as isize / 2)) as f32).abs().powi(2)
-        ).sqrt();
-        let tile_height = heightmap.get(coord) - ((distance_from_centre / (width / 2) as f32).powi(4) * 2.0);
+        let coord = noise_coord(x, y);
+        let tile_height = tile_height(&heightmap, x, y, width, height);
         Tile {
-            x: x,
-            y: y,
+            x,
+            y,
             style: if tile_height > 0.75 {
                 if rand.next_f32() > (((tile_height - 0.65) / 0.4) * 0.8) {
-                    TileStyle::RockLow
+                    rock_low
                 } else {
-                    TileStyle::RockHigh
+                    rock_high
                 }
             } else if tile_height > 0.55 {
-                TileStyle::Dirt
+                dirt
             } else if tile_height > -0.5 {
                 let tile_farmland = farmland.get(coord);
                 if tile_farmland > 0.7 {
-                    TileStyle::DirtFarmed
+                    dirt_farmed
                 } else {
                     let tile_vegetation = vegetation.get(coord);
                     if tile_vegetation > 0.6 {
                         if rand.next_f32() > 0.35 {
-                            TileStyle::GrassPlain
+                            grass_plain
                         } else {
-                            TileStyle::Tree
+                            tree
                         }
                     } else if tile_vegetation > 0.5 {
                         if rand.next_f32() > 0.05 {
-                            TileStyle::GrassPlain
+                            grass_plain
                         } else {
-                            TileStyle::Tree
+                            tree
                         }
                     } else {
-                        TileStyle::GrassCoastal
+                        grass_coastal
                     }
                 }
             } else if tile_height > -0.6 {
-                TileStyle::GrassCoastal
+                grass_coastal
             } else if tile_height > -0.7 {
-                TileStyle::Sand
+                sand
             } else if tile_height > -0.95 {
-                TileStyle::WaterShallow
+                water_shallow
             } else {
-                TileStyle::WaterDeep
+                water_deep
             },
             rand_offset: rand.next_f32(),
         }
@@ -80,9 +150,559 @@ pub fn generate_tilemap(width: usize, height: usize) -> TileMap {
     map
 }
 
-pub fn tile_is_passable(tile: &Tile) -> bool {
-    match tile.style {
-        TileStyle::WaterShallow | TileStyle::WaterDeep => false,
-        _ => true,
+pub fn tile_is_passable(map: &TileMap, tile: &Tile) -> bool {
+    map.tile_type(tile).passable
+}
+
+/// Tile types that fences and gates can be built on.
+const BUILDABLE: [&str; 5] = ["dirt", "dirt-farmed", "grass-plain", "grass-coastal", "sand"];
+
+/// Builds a fence on a tile, or pulls down the fence or gate already there.
+/// Only bare ground can be built on; pulled down fences leave dirt behind.
+pub fn toggle_fence(map: &mut TileMap, x: usize, y: usize) {
+    let types = map.types.clone();
+    let id = match map.get_tile(x, y) {
+        Some(tile) => types.get(tile.style).id.as_str(),
+        None => return,
+    };
+    match id {
+        "fence" | "gate" | "gate-open" => map.set_tile_style(x, y, types.style("dirt")),
+        _ if BUILDABLE.contains(&id) => map.set_tile_style(x, y, types.style("fence")),
+        _ => {}
+    }
+}
+
+/// Opens or shuts a gate, turns a fence into a shut gate, or builds a shut
+/// gate on bare ground.
+pub fn toggle_gate(map: &mut TileMap, x: usize, y: usize) {
+    let types = map.types.clone();
+    let id = match map.get_tile(x, y) {
+        Some(tile) => types.get(tile.style).id.as_str(),
+        None => return,
+    };
+    match id {
+        "gate" => map.set_tile_style(x, y, types.style("gate-open")),
+        "gate-open" => map.set_tile_style(x, y, types.style("gate")),
+        _ if id == "fence" || BUILDABLE.contains(&id) => map.set_tile_style(x, y, types.style("gate")),
+        _ => {}
+    }
+}
+
+/// Flood fills the map from its edges, stopping at fences and shut gates.
+/// Anything the fill can't reach is inside a pen. Water doesn't stop the
+/// fill, so a pen has to be fenced all the way round to count.
+pub fn enclosed_tiles(map: &TileMap) -> Vec<bool> {
+    let (fence, gate) = (map.types.style("fence"), map.types.style("gate"));
+    let is_wall = |index: usize| map.tiles[index].style == fence || map.tiles[index].style == gate;
+    let mut outside = vec![false; map.tiles.len()];
+    let mut stack = Vec::new();
+    for x in 0..map.width {
+        stack.push(x);
+        stack.push((map.height - 1) * map.width + x);
+    }
+    for y in 0..map.height {
+        stack.push(y * map.width);
+        stack.push(y * map.width + map.width - 1);
+    }
+    while let Some(index) = stack.pop() {
+        if outside[index] || is_wall(index) {
+            continue;
+        }
+        outside[index] = true;
+        let (x, y) = (index % map.width, index / map.width);
+        if x > 0 {
+            stack.push(index - 1);
+        }
+        if x + 1 < map.width {
+            stack.push(index + 1);
+        }
+        if y > 0 {
+            stack.push(index - map.width);
+        }
+        if y + 1 < map.height {
+            stack.push(index + map.width);
+        }
+    }
+    (0..map.tiles.len()).map(|index| !outside[index] && !is_wall(index)).collect()
+}
+
+/// The type of the tile under a fractional map position, unless it's off
+/// the edge of the map.
+pub fn point_tile_type(map: &TileMap, x: f32, y: f32) -> Option<&TileType> {
+    let (x, y) = (x.round(), y.round());
+    if x < 0.0 || y < 0.0 || x >= map.width as f32 || y >= map.height as f32 {
+        return None;
+    }
+    map.get_tile(x as usize, y as usize).map(|tile| map.tile_type(tile))
+}
+
+/// Whether the tile under a fractional map position can be walked on.
+/// Anything off the edge of the map is impassable.
+pub fn point_is_passable(map: &TileMap, x: f32, y: f32) -> bool {
+    point_tile_type(map, x, y).is_some_and(|tile_type| tile_type.passable)
+}
+
+/// Scatters a herd of sheep on passable tiles around a map point. Positions
+/// are drawn from the map seed, so a given world always starts with the same
+/// herd.
+pub fn spawn_flock(world: &mut World, map: &TileMap, x: usize, y: usize, count: usize) {
+    let mut rand = seeded_rng(map.seed.wrapping_add(1));
+    let mut spawned = 0;
+    let mut attempts = 0;
+    while spawned < count && attempts < count * 20 {
+        attempts += 1;
+        let sheep_x = x as f32 + rand.gen_range(-8.0, 8.0);
+        let sheep_y = y as f32 + rand.gen_range(-8.0, 8.0);
+        if !point_is_passable(map, sheep_x, sheep_y) {
+            continue;
+        }
+        let heading = rand.gen_range(-PI, PI);
+        world.create_entity()
+            .with(Sheep)
+            .with(Position { x: sheep_x, y: sheep_y })
+            .with(Velocity { x: 0.0, y: 0.0 })
+            .with(Heading(heading))
+            .build();
+        spawned += 1;
+    }
+}
+
+/// Puts a sheepdog on the nearest passable tile to a map point.
+pub fn spawn_dog(world: &mut World, map: &TileMap, x: usize, y: usize) {
+    for radius in 1..10 {
+        for &(dx, dy) in &[(radius, 0), (0, radius), (-radius, 0), (0, -radius)] {
+            let dog_x = (x as isize + dx) as f32;
+            let dog_y = (y as isize + dy) as f32;
+            if point_is_passable(map, dog_x, dog_y) {
+                world.create_entity()
+                    .with(Dog::default())
+                    .with(Threat)
+                    .with(Position { x: dog_x, y: dog_y })
+                    .build();
+                return;
+            }
+        }
+    }
+}
+
+/// The player's avatar, made on the player's tile if there isn't one yet.
+pub fn avatar(world: &mut World) -> Entity {
+    let existing = (&*world.entities(), &world.read::<Avatar>()).join().map(|(entity, _)| entity).next();
+    match existing {
+        Some(entity) => entity,
+        None => {
+            let position = world.read_resource::<PlayerPosition>().0;
+            world.create_entity().with(Avatar).with(position).build()
+        }
+    }
+}
+
+/// What to call an entity in the log.
+fn describe(world: &World, entity: Entity) -> &'static str {
+    if world.read::<Avatar>().get(entity).is_some() {
+        "You"
+    } else if world.read::<Dog>().get(entity).is_some() {
+        "The dog"
+    } else if world.read::<Wolf>().get(entity).is_some() {
+        "A wolf"
+    } else if world.read::<Sheep>().get(entity).is_some() {
+        "A sheep"
+    } else {
+        "Someone"
+    }
+}
+
+/// Has an entity say something for a while of game time, and notes it in
+/// the log.
+pub fn say(world: &mut World, speaker: Entity, text: &str, lasting: Duration) {
+    let now = world.read_resource::<GameClock>().now;
+    let dead_at = now + lasting;
+    let message = format!("{}: \"{}\"", describe(world, speaker), text);
+    world.write_resource::<EventLog>().push(now, message);
+    let position = world.read::<Position>().get(speaker).cloned();
+    let mut utterance = world.create_entity()
+        .with(Utterance { speaker: Some(speaker), text: String::from(text), dead_at });
+    if let Some(position) = position {
+        utterance = utterance.with(position);
+    }
+    utterance.build();
+}
+
+fn passable_neighbours(map: &TileMap, (x, y): (usize, usize)) -> Vec<(usize, usize)> {
+    let mut neighbours = Vec::with_capacity(4);
+    if y > 0 {
+        neighbours.push((x, y - 1));
+    }
+    if x + 1 < map.width {
+        neighbours.push((x + 1, y));
+    }
+    if y + 1 < map.height {
+        neighbours.push((x, y + 1));
+    }
+    if x > 0 {
+        neighbours.push((x - 1, y));
+    }
+    neighbours.into_iter()
+        .filter(|&(x, y)| map.get_tile(x, y).is_some_and(|tile| tile_is_passable(map, tile)))
+        .collect()
+}
+
+/// A tile waiting to be searched, ordered so the one that's cheapest to
+/// reach comes out of a `BinaryHeap` first.
+#[derive(PartialEq)]
+struct Frontier {
+    cost: f32,
+    tile: (usize, usize),
+}
+
+impl Eq for Frontier {}
+
+impl Ord for Frontier {
+    fn cmp(&self, other: &Frontier) -> cmp::Ordering {
+        other.cost.total_cmp(&self.cost).then_with(|| other.tile.cmp(&self.tile))
+    }
+}
+
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Frontier) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Finds the first tile to step onto on the way from one tile to another,
+/// searching passable tiles cheapest first by their movement cost, so
+/// routes go round rock and woods where that's quicker. The search gives up
+/// after reaching `max_tiles` tiles; if the target wasn't reached by then
+/// (or can't be stood on) this heads for the closest tile that was.
+pub fn next_step_towards(map: &TileMap, from: (usize, usize), to: (usize, usize), max_tiles: usize) -> Option<(usize, usize)> {
+    let distance_sq = |(x, y): (usize, usize)| {
+        let dx = x as isize - to.0 as isize;
+        let dy = y as isize - to.1 as isize;
+        dx * dx + dy * dy
+    };
+    let mut came_from = HashMap::new();
+    let mut costs = HashMap::new();
+    let mut frontier = BinaryHeap::new();
+    came_from.insert(from, from);
+    costs.insert(from, 0.0);
+    frontier.push(Frontier { cost: 0.0, tile: from });
+    let mut closest = from;
+    while let Some(Frontier { cost, tile }) = frontier.pop() {
+        // A cheaper way here was found after this one was queued.
+        if cost > costs[&tile] {
+            continue;
+        }
+        if distance_sq(tile) < distance_sq(closest) {
+            closest = tile;
+        }
+        if tile == to {
+            break;
+        }
+        if came_from.len() >= max_tiles {
+            continue;
+        }
+        for (x, y) in passable_neighbours(map, tile) {
+            let cost = cost + map.tile_type(map.get_tile(x, y).unwrap()).movement_cost;
+            if costs.get(&(x, y)).is_none_or(|&known| cost < known) {
+                costs.insert((x, y), cost);
+                came_from.insert((x, y), tile);
+                frontier.push(Frontier { cost, tile: (x, y) });
+            }
+        }
+    }
+    if closest == from {
+        return None;
+    }
+    // Walk the path back to the tile just after `from`.
+    let mut step = closest;
+    while came_from[&step] != from {
+        step = came_from[&step];
+    }
+    Some(step)
+}
+
+/// Tiles wolves can come out of: high rock and woodland.
+pub fn wolf_dens(map: &TileMap) -> Vec<(usize, usize)> {
+    let (rock_high, tree) = (map.types.style("rock-high"), map.types.style("tree"));
+    map.tiles.iter()
+        .filter(|tile| tile.style == rock_high || tile.style == tree)
+        .map(|tile| (tile.x, tile.y))
+        .collect()
+}
+
+/// Where something is from the player, as the player would be told.
+fn whereabouts(dx: f32, dy: f32) -> String {
+    let distance = (dx * dx + dy * dy).sqrt().round();
+    if distance < 1.0 {
+        return String::from("right here");
+    }
+    let tiles = if distance == 1.0 { "tile" } else { "tiles" };
+    format!("{} {} {}", distance, tiles, Direction::towards(dx, dy).name())
+}
+
+/// Says how many of some animal the player can see and where the nearest
+/// is, if there are any.
+fn describe_animals(singular: &str, plural: &str, offsets: &[(f32, f32)]) -> Option<String> {
+    let &(dx, dy) = offsets.iter().min_by(|a, b| {
+        (a.0 * a.0 + a.1 * a.1).partial_cmp(&(b.0 * b.0 + b.1 * b.1)).unwrap()
+    })?;
+    Some(match offsets.len() {
+        1 => format!("{} is {}", singular, whereabouts(dx, dy)),
+        count => format!("{} {}, the nearest {}", count, plural, whereabouts(dx, dy)),
+    })
+}
+
+/// A few lines of text on what's around a map point: what the player is
+/// standing on, the terrain nearby and which way it lies, and the animals
+/// in sight. Nothing beyond the light is mentioned. Stands in for looking
+/// at the screen.
+pub fn describe_surroundings(world: &World, map: &TileMap, x: usize, y: usize) -> Vec<String> {
+    let lighting = Lighting::at(world.read_resource::<GameClock>().time_of_day());
+    let seen = |dx: f32, dy: f32, radius: f32| {
+        let distance = (dx * dx + dy * dy).sqrt();
+        distance <= radius && lighting.brightness_at(distance) > 0.0
+    };
+    let mut lines = Vec::new();
+    let here = match map.get_tile(x, y) {
+        Some(tile) => map.tile_type(tile).name.clone(),
+        None => return lines,
+    };
+
+    // Tiles of each type in sight, and their total offset from the player.
+    let radius = DESCRIBE_TERRAIN_RADIUS as usize;
+    let mut terrain: Vec<(&TileType, usize, f32, f32)> = Vec::new();
+    let mut total = 0;
+    for tile_y in y.saturating_sub(radius)..cmp::min(y + radius + 1, map.height) {
+        for tile_x in x.saturating_sub(radius)..cmp::min(x + radius + 1, map.width) {
+            let (dx, dy) = (tile_x as f32 - x as f32, tile_y as f32 - y as f32);
+            if !seen(dx, dy, DESCRIBE_TERRAIN_RADIUS) {
+                continue;
+            }
+            let tile_type = map.tile_type(map.get_tile(tile_x, tile_y).unwrap());
+            match terrain.iter_mut().find(|entry| entry.0.id == tile_type.id) {
+                Some(entry) => {
+                    entry.1 += 1;
+                    entry.2 += dx;
+                    entry.3 += dy;
+                }
+                None => terrain.push((tile_type, 1, dx, dy)),
+            }
+            total += 1;
+        }
+    }
+    terrain.sort_by_key(|&(_, count, _, _)| cmp::Reverse(count));
+    let around: Vec<String> = terrain.iter()
+        .take(DESCRIBE_TERRAIN_TYPES)
+        .filter_map(|&(tile_type, count, dx, dy)| {
+            let share = count as f32 / total as f32;
+            let amount = if share >= 0.5 {
+                "mostly"
+            } else if share >= 0.2 {
+                "some"
+            } else if share >= 0.05 {
+                "a little"
+            } else {
+                return None;
+            };
+            // Terrain whose middle is well off to one side lies that way.
+            let (dx, dy) = (dx / count as f32, dy / count as f32);
+            if (dx * dx + dy * dy).sqrt() >= DESCRIBE_TERRAIN_RADIUS / 3.0 {
+                Some(format!("{} {} {}", amount, tile_type.name, Direction::towards(dx, dy).name()))
+            } else {
+                Some(format!("{} {}", amount, tile_type.name))
+            }
+        })
+        .collect();
+    lines.push(format!("On {}; around, {}.", here, around.join(", ")));
+
+    let positions = world.read::<Position>();
+    let offsets_in_sight = |found: Vec<&Position>| -> Vec<(f32, f32)> {
+        found.into_iter()
+            .map(|position| (position.x - x as f32, position.y - y as f32))
+            .filter(|&(dx, dy)| seen(dx, dy, DESCRIBE_ANIMALS_RADIUS))
+            .collect()
+    };
+    let sheep = offsets_in_sight((&world.read::<Sheep>(), &positions).join().map(|(_, position)| position).collect());
+    let dogs = offsets_in_sight((&world.read::<Dog>(), &positions).join().map(|(_, position)| position).collect());
+    let wolves = offsets_in_sight((&world.read::<Wolf>(), &positions).join().map(|(_, position)| position).collect());
+    let animals: Vec<String> = [
+        describe_animals("A sheep", "sheep", &sheep),
+        describe_animals("The dog", "dogs", &dogs),
+        describe_animals("A wolf", "wolves", &wolves),
+    ].iter().flatten().cloned().collect();
+    lines.push(if animals.is_empty() {
+        String::from("No animals in sight.")
+    } else {
+        format!("{}.", animals.join(". "))
+    });
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use time::Duration;
+
+    use tile::{Tile, TileMap};
+
+    use tile_types::TileTypes;
+
+    use ecs_entities::{Position, Sheep, Dog};
+
+    use ecs_systems::{self, GameClock, DAY_LENGTH};
+
+    use super::{describe_surroundings, enclosed_tiles, generate_tilemap, next_step_towards};
+
+    /// A map drawn as rows of text: '#' is fence, 'g' a shut gate, '/' an
+    /// open gate, '~' water, 'r' high rock and anything else grass.
+    fn drawn_map(rows: &[&str]) -> TileMap {
+        let types = Arc::new(TileTypes::default());
+        let mut map = TileMap::new(rows[0].len(), rows.len(), 0, types.clone());
+        map.fill_tiles(|x, y| {
+            let id = match rows[y].as_bytes()[x] {
+                b'#' => "fence",
+                b'g' => "gate",
+                b'/' => "gate-open",
+                b'~' => "water-shallow",
+                b'r' => "rock-high",
+                _ => "grass-plain",
+            };
+            Tile { x, y, style: types.style(id), rand_offset: 0.0 }
+        });
+        map
+    }
+
+    /// The enclosed tiles drawn as 'x's over dots.
+    fn drawn_enclosure(map: &TileMap) -> Vec<String> {
+        enclosed_tiles(map)
+            .chunks(map.width)
+            .map(|row| row.iter().map(|&enclosed| if enclosed { 'x' } else { '.' }).collect())
+            .collect()
+    }
+
+    fn tiles(map: &TileMap) -> Vec<(u16, u32)> {
+        map.tiles.iter().map(|tile| (tile.style.0, tile.rand_offset.to_bits())).collect()
+    }
+
+    #[test]
+    fn same_seed_generates_same_map() {
+        let types = Arc::new(TileTypes::default());
+        let first = generate_tilemap(64, 48, 7, types.clone());
+        let second = generate_tilemap(64, 48, 7, types);
+        assert_eq!(tiles(&first), tiles(&second));
+    }
+
+    #[test]
+    fn different_seeds_generate_different_maps() {
+        let types = Arc::new(TileTypes::default());
+        let first = generate_tilemap(64, 48, 7, types.clone());
+        let second = generate_tilemap(64, 48, 8, types);
+        assert_ne!(tiles(&first), tiles(&second));
+    }
+
+    #[test]
+    fn fenced_ground_is_enclosed() {
+        let map = drawn_map(&[
+            ".......",
+            ".#####.",
+            ".#...#.",
+            ".#...g.",
+            ".#####.",
+            ".......",
+        ]);
+        assert_eq!(drawn_enclosure(&map), [
+            ".......",
+            ".......",
+            "..xxx..",
+            "..xxx..",
+            ".......",
+            ".......",
+        ]);
+    }
+
+    #[test]
+    fn open_gate_lets_the_outside_in() {
+        let map = drawn_map(&[
+            ".......",
+            ".#####.",
+            ".#...#.",
+            ".#.../.",
+            ".#####.",
+            ".......",
+        ]);
+        assert!(!enclosed_tiles(&map).contains(&true));
+    }
+
+    #[test]
+    fn water_does_not_close_a_pen() {
+        let map = drawn_map(&[
+            ".......",
+            ".###~~.",
+            ".#...~.",
+            ".#####.",
+            ".......",
+        ]);
+        assert!(!enclosed_tiles(&map).contains(&true));
+    }
+
+    #[test]
+    fn map_edge_does_not_close_a_pen() {
+        let map = drawn_map(&[
+            "...#...",
+            "...#...",
+            "####...",
+            ".......",
+        ]);
+        assert!(!enclosed_tiles(&map).contains(&true));
+    }
+
+    #[test]
+    fn routes_round_costly_ground() {
+        let map = drawn_map(&[
+            ".rrr.",
+            ".....",
+        ]);
+        assert_eq!(next_step_towards(&map, (0, 0), (4, 0), 100), Some((0, 1)));
+    }
+
+    #[test]
+    fn crosses_costly_ground_when_it_is_quicker() {
+        let map = drawn_map(&[
+            ".r.",
+            "...",
+        ]);
+        assert_eq!(next_step_towards(&map, (0, 0), (2, 0), 100), Some((1, 0)));
+    }
+
+    /// Grass with a strip of water along the bottom, the player in the
+    /// middle near the top, two sheep and the dog.
+    fn describe_at(time_of_day: f32) -> Vec<String> {
+        let mut rows = vec![".".repeat(31); 8];
+        rows.extend(vec!["~".repeat(31); 3]);
+        let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+        let map = drawn_map(&rows);
+        let mut world = ecs_systems::new_world();
+        world.write_resource::<GameClock>().now = Duration::milliseconds((DAY_LENGTH as f32 * 1000.0 * time_of_day) as i64);
+        world.create_entity().with(Sheep).with(Position { x: 18.0, y: 3.0 }).build();
+        world.create_entity().with(Sheep).with(Position { x: 15.0, y: 23.0 }).build();
+        world.create_entity().with(Dog::default()).with(Position { x: 15.0, y: 2.0 }).build();
+        describe_surroundings(&world, &map, 15, 3)
+    }
+
+    #[test]
+    fn describes_terrain_and_animals() {
+        assert_eq!(describe_at(0.4), [
+            "On grass; around, mostly grass, some shallow water south.",
+            "2 sheep, the nearest 3 tiles east. The dog is 1 tile north.",
+        ]);
+    }
+
+    #[test]
+    fn leaves_out_what_the_dark_hides() {
+        assert_eq!(describe_at(0.8), [
+            "On grass; around, mostly grass, some shallow water south.",
+            "A sheep is 3 tiles east. The dog is 1 tile north.",
+        ]);
     }
 }
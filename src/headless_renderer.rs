@@ -0,0 +1,140 @@
+use std::cell::{Cell, Ref, RefCell};
+use std::mem;
+
+use specs::World;
+
+use tile::TileMap;
+use tile::TileMapView;
+
+use player::Player;
+
+use renderable::Renderable;
+
+use lighting::Lighting;
+
+use cell_buffer::CellBuffer;
+
+use terminal_renderer::{self, HUD_ROWS_ABOVE, HUD_ROWS_BELOW};
+
+use export::ExportError;
+
+/// Draws frames exactly as the terminal renderer would, but into memory, so
+/// the game can run without a terminal and frames can be looked at after.
+pub struct HeadlessRenderer {
+    /// The frame being drawn.
+    back: RefCell<CellBuffer>,
+    /// The last frame presented.
+    front: RefCell<CellBuffer>,
+    /// Whether the starting size has been reported yet.
+    sized: Cell<bool>,
+    /// Whether to draw plain glyphs, as the terminal renderer does without
+    /// colour.
+    plain: bool,
+}
+
+impl HeadlessRenderer {
+    /// A renderer with room for a view of the given size plus the HUD.
+    pub fn new(view_width: usize, view_height: usize, plain: bool) -> HeadlessRenderer {
+        let rows = view_height + HUD_ROWS_ABOVE + HUD_ROWS_BELOW;
+        HeadlessRenderer {
+            back: RefCell::new(CellBuffer::new(view_width, rows)),
+            front: RefCell::new(CellBuffer::new(view_width, rows)),
+            sized: Cell::new(false),
+            plain,
+        }
+    }
+
+    /// The last frame presented.
+    pub fn frame(&self) -> Ref<'_, CellBuffer> {
+        self.front.borrow()
+    }
+}
+
+impl Renderable for HeadlessRenderer {
+    fn set_up(&self) {}
+
+    fn poll_resize(&self) -> Option<(usize, usize)> {
+        if self.sized.replace(true) {
+            return None;
+        }
+        let front = self.front.borrow();
+        Some((front.width, front.height - HUD_ROWS_ABOVE - HUD_ROWS_BELOW))
+    }
+
+    fn render_map(&self, map: &TileMap, map_view: &TileMapView, player: &Player, &rand: &f32, lighting: &Lighting) {
+        terminal_renderer::draw_map(&mut self.back.borrow_mut(), map, map_view, player, rand, lighting, self.plain);
+    }
+
+    fn render_world(&self, world: &World, map_view: &TileMapView, lighting: &Lighting) {
+        terminal_renderer::draw_world(&mut self.back.borrow_mut(), world, map_view, lighting, self.plain);
+    }
+
+    fn export(&self, title: &str, html_path: &str, ans_path: &str) -> Result<(), ExportError> {
+        terminal_renderer::export_view(&self.back.borrow(), title, html_path, ans_path, self.plain)
+    }
+
+    fn present(&self) {
+        mem::swap(&mut *self.front.borrow_mut(), &mut *self.back.borrow_mut());
+    }
+
+    fn tear_down(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use flock::generate_tilemap;
+
+    use tile::TileMapView;
+
+    use tile_types::TileTypes;
+
+    use player::Player;
+
+    use renderable::Renderable;
+
+    use lighting::Lighting;
+
+    use terminal_renderer::HUD_ROWS_ABOVE;
+
+    use super::HeadlessRenderer;
+
+    #[test]
+    fn renders_map_around_player() {
+        let map = generate_tilemap(80, 60, 7, Arc::new(TileTypes::default()));
+        let player = Player::new(40, 30, map.width - 1, map.height - 1);
+        let renderer = HeadlessRenderer::new(24, 8, false);
+        let mut view = TileMapView::new(&map, 24, 8);
+        view.centre_on_map_point(player.x, player.y);
+        renderer.poll_resize();
+        renderer.render_map(&map, &view, &player, &0.0, &Lighting::daylight());
+        renderer.present();
+
+        let frame = renderer.frame();
+        assert_eq!(frame.text(), [
+            "40,30 seed 7",
+            " .  . ~          ~",
+            "-  .. ~~    ~~  ~",
+            ".. , .  .~.        ~",
+            "  ... , . .~~-",
+            "..  .     . &      ~",
+            "  ..       , ~ .   -~-",
+            " .   .  ., .      . ..",
+            "...       ,..,  .. .  .",
+            "---- run [##########] --",
+            "",
+            "",
+            "",
+            "",
+            "",
+        ].join("\n"));
+        let player_cell = frame.get(12, 4 + HUD_ROWS_ABOVE).unwrap();
+        assert_eq!((player_cell.glyph, player_cell.fg, player_cell.bg), ('&', [255, 255, 255], [0, 0, 0]));
+        // The top left of the view is the map 12 tiles left of the player and
+        // four up, lit by broad daylight.
+        let tile_type = map.tile_type(map.get_tile(28, 26).unwrap());
+        let tile_cell = frame.get(0, HUD_ROWS_ABOVE).unwrap();
+        assert_eq!((tile_cell.fg, tile_cell.bg), (tile_type.colour_fg, tile_type.colour_bg));
+    }
+}
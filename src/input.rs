@@ -0,0 +1,276 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+
+use termion::input::{Keys, TermRead};
+
+use keybindings::KeyBindings;
+
+use player::Direction;
+
+/// Everything the player can ask the game to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Move(Direction),
+    Run(Direction),
+    ToggleFence,
+    ToggleGate,
+    ComeBye,
+    AwayToMe,
+    WalkUp,
+    LieDown,
+    ThatllDo,
+    TogglePause,
+    SpeedUp,
+    SlowDown,
+    Save,
+    Load,
+    Speak,
+    LogOlder,
+    LogNewer,
+    ToggleMinimap,
+    /// Opens or closes the full-screen map. While it's open, moving pans
+    /// the map cursor instead of the player.
+    ToggleMap,
+    ZoomIn,
+    ZoomOut,
+    /// Notes what's around the player in the log, for playing without
+    /// looking at the map.
+    Describe,
+    /// Writes the view being shown out as a web page and ANSI art.
+    Export,
+    Help,
+    /// Does nothing, letting the world carry on for a moment.
+    Wait,
+    Quit,
+}
+
+/// Every action with the name scripts and config files know it by.
+pub const ACTION_NAMES: [(Action, &str); 40] = [
+    (Action::Move(Direction::North), "move-north"),
+    (Action::Move(Direction::NorthEast), "move-north-east"),
+    (Action::Move(Direction::East), "move-east"),
+    (Action::Move(Direction::SouthEast), "move-south-east"),
+    (Action::Move(Direction::South), "move-south"),
+    (Action::Move(Direction::SouthWest), "move-south-west"),
+    (Action::Move(Direction::West), "move-west"),
+    (Action::Move(Direction::NorthWest), "move-north-west"),
+    (Action::Run(Direction::North), "run-north"),
+    (Action::Run(Direction::NorthEast), "run-north-east"),
+    (Action::Run(Direction::East), "run-east"),
+    (Action::Run(Direction::SouthEast), "run-south-east"),
+    (Action::Run(Direction::South), "run-south"),
+    (Action::Run(Direction::SouthWest), "run-south-west"),
+    (Action::Run(Direction::West), "run-west"),
+    (Action::Run(Direction::NorthWest), "run-north-west"),
+    (Action::ToggleFence, "toggle-fence"),
+    (Action::ToggleGate, "toggle-gate"),
+    (Action::ComeBye, "come-bye"),
+    (Action::AwayToMe, "away-to-me"),
+    (Action::WalkUp, "walk-up"),
+    (Action::LieDown, "lie-down"),
+    (Action::ThatllDo, "thatll-do"),
+    (Action::TogglePause, "toggle-pause"),
+    (Action::SpeedUp, "speed-up"),
+    (Action::SlowDown, "slow-down"),
+    (Action::Save, "save"),
+    (Action::Load, "load"),
+    (Action::Speak, "speak"),
+    (Action::LogOlder, "log-older"),
+    (Action::LogNewer, "log-newer"),
+    (Action::ToggleMinimap, "minimap"),
+    (Action::ToggleMap, "map"),
+    (Action::ZoomIn, "zoom-in"),
+    (Action::ZoomOut, "zoom-out"),
+    (Action::Describe, "describe"),
+    (Action::Export, "export"),
+    (Action::Help, "help"),
+    (Action::Wait, "wait"),
+    (Action::Quit, "quit"),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTION_NAMES.iter().find(|&&(_, action_name)| action_name == name).map(|&(action, _)| action)
+    }
+
+    pub fn name(self) -> &'static str {
+        ACTION_NAMES.iter().find(|&&(action, _)| action == self).map(|&(_, name)| name).unwrap()
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Somewhere actions come from. `None` means there will never be any more.
+pub trait InputSource {
+    fn next_action(&mut self) -> Option<Action>;
+}
+
+/// Key presses from a terminal, blocking until the next bound one. Keys
+/// that aren't bound to anything are ignored.
+pub struct TermInput<R: Read> {
+    keys: Keys<R>,
+    bindings: KeyBindings,
+}
+
+impl<R: Read> TermInput<R> {
+    pub fn new(input: R, bindings: KeyBindings) -> TermInput<R> {
+        TermInput {
+            keys: input.keys(),
+            bindings,
+        }
+    }
+}
+
+impl<R: Read> InputSource for TermInput<R> {
+    fn next_action(&mut self) -> Option<Action> {
+        while let Some(Ok(key)) = self.keys.next() {
+            if let Some(action) = self.bindings.action_for(key) {
+                return Some(action);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct ScriptError(String);
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<io::Error> for ScriptError {
+    fn from(error: io::Error) -> ScriptError {
+        ScriptError(error.to_string())
+    }
+}
+
+/// A fixed list of actions, for driving the game without anyone at the
+/// keyboard.
+///
+/// Scripts have one action name per line, optionally followed by how many
+/// times to do it. Blank lines and anything after a `#` are ignored:
+///
+/// ```text
+/// move-north 5
+/// come-bye
+/// wait 600    # let the dog work
+/// ```
+#[derive(Default)]
+pub struct ScriptedInput {
+    /// Each action with how many more times to do it.
+    actions: VecDeque<(Action, usize)>,
+}
+
+impl ScriptedInput {
+    pub fn parse(script: &str) -> Result<ScriptedInput, ScriptError> {
+        let mut actions = VecDeque::new();
+        for (number, line) in script.lines().enumerate() {
+            let line = line.split('#').next().unwrap();
+            let mut words = line.split_whitespace();
+            let name = match words.next() {
+                Some(name) => name,
+                None => continue,
+            };
+            let action = Action::from_name(name)
+                .ok_or_else(|| ScriptError(format!("line {}: unknown action '{}'", number + 1, name)))?;
+            let times = match words.next() {
+                Some(times) => times.parse()
+                    .map_err(|_| ScriptError(format!("line {}: expected a count, got '{}'", number + 1, times)))?,
+                None => 1,
+            };
+            if let Some(extra) = words.next() {
+                return Err(ScriptError(format!("line {}: unexpected '{}'", number + 1, extra)));
+            }
+            if times > 0 {
+                actions.push_back((action, times));
+            }
+        }
+        Ok(ScriptedInput { actions })
+    }
+
+    pub fn read(path: &str) -> Result<ScriptedInput, ScriptError> {
+        let mut script = String::new();
+        File::open(path)?.read_to_string(&mut script)?;
+        ScriptedInput::parse(&script)
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn next_action(&mut self) -> Option<Action> {
+        let &mut (action, ref mut remaining) = self.actions.front_mut()?;
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.actions.pop_front();
+        }
+        Some(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use player::Direction;
+
+    use super::{Action, InputSource, ScriptedInput};
+
+    fn actions(script: &str) -> Vec<Action> {
+        let mut input = ScriptedInput::parse(script).unwrap();
+        let mut actions = Vec::new();
+        while let Some(action) = input.next_action() {
+            actions.push(action);
+        }
+        actions
+    }
+
+    fn error(script: &str) -> String {
+        ScriptedInput::parse(script).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn repeats_counted_actions() {
+        assert_eq!(actions("move-north 3\ncome-bye\nwait 0\nlie-down 1"), [
+            Action::Move(Direction::North),
+            Action::Move(Direction::North),
+            Action::Move(Direction::North),
+            Action::ComeBye,
+            Action::LieDown,
+        ]);
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        assert_eq!(actions("# setting off\n\n  run-east 2  # quickly\n   \nquit"), [
+            Action::Run(Direction::East),
+            Action::Run(Direction::East),
+            Action::Quit,
+        ]);
+    }
+
+    #[test]
+    fn counts_down_long_repeats_lazily() {
+        let mut input = ScriptedInput::parse("wait 4000000000\nquit").unwrap();
+        assert_eq!(input.next_action(), Some(Action::Wait));
+        assert_eq!(input.actions.front(), Some(&(Action::Wait, 3_999_999_999)));
+    }
+
+    #[test]
+    fn rejects_unknown_actions() {
+        assert_eq!(error("wait\nfly-north"), "line 2: unknown action 'fly-north'");
+    }
+
+    #[test]
+    fn rejects_bad_counts() {
+        assert_eq!(error("wait lots"), "line 1: expected a count, got 'lots'");
+        assert_eq!(error("wait -1"), "line 1: expected a count, got '-1'");
+        assert_eq!(error("wait 2 3"), "line 1: unexpected '3'");
+    }
+}
@@ -0,0 +1,370 @@
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::PathBuf;
+
+use termion::event::Key;
+
+use input::{Action, ACTION_NAMES};
+
+use player::Direction;
+
+pub const DEFAULT_PRESET: &str = "wasd";
+
+/// Bindings every preset shares, on top of its movement keys.
+const COMMON_BINDINGS: [(Key, Action); 28] = [
+    (Key::Up, Action::Move(Direction::North)),
+    (Key::Right, Action::Move(Direction::East)),
+    (Key::Down, Action::Move(Direction::South)),
+    (Key::Left, Action::Move(Direction::West)),
+    (Key::Char('F'), Action::ToggleFence),
+    (Key::Char('G'), Action::ToggleGate),
+    (Key::Char('v'), Action::ComeBye),
+    (Key::Char('x'), Action::AwayToMe),
+    (Key::Char('r'), Action::WalkUp),
+    (Key::Char('f'), Action::LieDown),
+    (Key::Char('t'), Action::ThatllDo),
+    (Key::Char('p'), Action::TogglePause),
+    (Key::Char('+'), Action::SpeedUp),
+    (Key::Char('='), Action::SpeedUp),
+    (Key::Char('-'), Action::SlowDown),
+    (Key::F(5), Action::Save),
+    (Key::F(9), Action::Load),
+    (Key::Char(' '), Action::Speak),
+    (Key::Char('['), Action::LogOlder),
+    (Key::Char(']'), Action::LogNewer),
+    (Key::Char('m'), Action::ToggleMinimap),
+    (Key::Char('M'), Action::ToggleMap),
+    (Key::Char('<'), Action::ZoomIn),
+    (Key::Char('>'), Action::ZoomOut),
+    (Key::Char('i'), Action::Describe),
+    (Key::F(12), Action::Export),
+    (Key::Char('?'), Action::Help),
+    (Key::Esc, Action::Quit),
+];
+
+/// Shifted letters run.
+const WASD_BINDINGS: [(Key, Action); 18] = [
+    (Key::Char('w'), Action::Move(Direction::North)),
+    (Key::Char('e'), Action::Move(Direction::NorthEast)),
+    (Key::Char('d'), Action::Move(Direction::East)),
+    (Key::Char('c'), Action::Move(Direction::SouthEast)),
+    (Key::Char('s'), Action::Move(Direction::South)),
+    (Key::Char('z'), Action::Move(Direction::SouthWest)),
+    (Key::Char('a'), Action::Move(Direction::West)),
+    (Key::Char('q'), Action::Move(Direction::NorthWest)),
+    (Key::Char('W'), Action::Run(Direction::North)),
+    (Key::Char('E'), Action::Run(Direction::NorthEast)),
+    (Key::Char('D'), Action::Run(Direction::East)),
+    (Key::Char('C'), Action::Run(Direction::SouthEast)),
+    (Key::Char('S'), Action::Run(Direction::South)),
+    (Key::Char('Z'), Action::Run(Direction::SouthWest)),
+    (Key::Char('A'), Action::Run(Direction::West)),
+    (Key::Char('Q'), Action::Run(Direction::NorthWest)),
+    (Key::Char('.'), Action::Wait),
+    (Key::Ctrl('c'), Action::Quit),
+];
+
+/// Shifted letters run.
+const VI_BINDINGS: [(Key, Action); 18] = [
+    (Key::Char('k'), Action::Move(Direction::North)),
+    (Key::Char('u'), Action::Move(Direction::NorthEast)),
+    (Key::Char('l'), Action::Move(Direction::East)),
+    (Key::Char('n'), Action::Move(Direction::SouthEast)),
+    (Key::Char('j'), Action::Move(Direction::South)),
+    (Key::Char('b'), Action::Move(Direction::SouthWest)),
+    (Key::Char('h'), Action::Move(Direction::West)),
+    (Key::Char('y'), Action::Move(Direction::NorthWest)),
+    (Key::Char('K'), Action::Run(Direction::North)),
+    (Key::Char('U'), Action::Run(Direction::NorthEast)),
+    (Key::Char('L'), Action::Run(Direction::East)),
+    (Key::Char('N'), Action::Run(Direction::SouthEast)),
+    (Key::Char('J'), Action::Run(Direction::South)),
+    (Key::Char('B'), Action::Run(Direction::SouthWest)),
+    (Key::Char('H'), Action::Run(Direction::West)),
+    (Key::Char('Y'), Action::Run(Direction::NorthWest)),
+    (Key::Char('.'), Action::Wait),
+    (Key::Ctrl('c'), Action::Quit),
+];
+
+/// Digits with num lock on, the navigation keys they send with it off.
+/// The keypad can't be shifted, so running is left to a bindings file.
+const NUMPAD_BINDINGS: [(Key, Action); 14] = [
+    (Key::Char('8'), Action::Move(Direction::North)),
+    (Key::Char('9'), Action::Move(Direction::NorthEast)),
+    (Key::Char('6'), Action::Move(Direction::East)),
+    (Key::Char('3'), Action::Move(Direction::SouthEast)),
+    (Key::Char('2'), Action::Move(Direction::South)),
+    (Key::Char('1'), Action::Move(Direction::SouthWest)),
+    (Key::Char('4'), Action::Move(Direction::West)),
+    (Key::Char('7'), Action::Move(Direction::NorthWest)),
+    (Key::PageUp, Action::Move(Direction::NorthEast)),
+    (Key::PageDown, Action::Move(Direction::SouthEast)),
+    (Key::End, Action::Move(Direction::SouthWest)),
+    (Key::Home, Action::Move(Direction::NorthWest)),
+    (Key::Char('5'), Action::Wait),
+    (Key::Ctrl('c'), Action::Quit),
+];
+
+#[derive(Debug)]
+pub struct BindingsError(String);
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<io::Error> for BindingsError {
+    fn from(error: io::Error) -> BindingsError {
+        BindingsError(error.to_string())
+    }
+}
+
+/// The name a key is written as in bindings files and the help overlay.
+pub fn key_name(key: Key) -> String {
+    match key {
+        Key::Char(' ') => String::from("space"),
+        Key::Char('\t') => String::from("tab"),
+        Key::Char('\n') => String::from("enter"),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("ctrl-{}", c),
+        Key::Alt(c) => format!("alt-{}", c),
+        Key::F(n) => format!("f{}", n),
+        Key::Up => String::from("up"),
+        Key::Down => String::from("down"),
+        Key::Left => String::from("left"),
+        Key::Right => String::from("right"),
+        Key::Home => String::from("home"),
+        Key::End => String::from("end"),
+        Key::PageUp => String::from("pageup"),
+        Key::PageDown => String::from("pagedown"),
+        Key::Insert => String::from("insert"),
+        Key::Delete => String::from("delete"),
+        Key::Backspace => String::from("backspace"),
+        Key::Esc => String::from("esc"),
+        _ => String::from("?"),
+    }
+}
+
+/// Reads a key name as written by `key_name`.
+pub fn parse_key(name: &str) -> Option<Key> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Key::Char(c));
+    }
+    let single = |rest: &str| {
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(c) = name.strip_prefix("ctrl-").and_then(single) {
+        return Some(Key::Ctrl(c));
+    }
+    if let Some(c) = name.strip_prefix("alt-").and_then(single) {
+        return Some(Key::Alt(c));
+    }
+    if let Some(number) = name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+        if (1..=12).contains(&number) {
+            return Some(Key::F(number));
+        }
+    }
+    match name {
+        "space" => Some(Key::Char(' ')),
+        "tab" => Some(Key::Char('\t')),
+        "enter" => Some(Key::Char('\n')),
+        "up" => Some(Key::Up),
+        "down" => Some(Key::Down),
+        "left" => Some(Key::Left),
+        "right" => Some(Key::Right),
+        "home" => Some(Key::Home),
+        "end" => Some(Key::End),
+        "pageup" => Some(Key::PageUp),
+        "pagedown" => Some(Key::PageDown),
+        "insert" => Some(Key::Insert),
+        "delete" => Some(Key::Delete),
+        "backspace" => Some(Key::Backspace),
+        "esc" => Some(Key::Esc),
+        _ => None,
+    }
+}
+
+/// Which key does what. A key does at most one thing; an action can have
+/// any number of keys.
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    bindings: Vec<(Key, Action)>,
+}
+
+impl KeyBindings {
+    /// One of the built-in layouts: `wasd`, `vi` or `numpad`.
+    pub fn preset(name: &str) -> Option<KeyBindings> {
+        let movement: &[(Key, Action)] = match name {
+            "wasd" => &WASD_BINDINGS,
+            "vi" => &VI_BINDINGS,
+            "numpad" => &NUMPAD_BINDINGS,
+            _ => return None,
+        };
+        Some(KeyBindings {
+            bindings: movement.iter().chain(COMMON_BINDINGS.iter()).cloned().collect(),
+        })
+    }
+
+    /// Reads a bindings file. Each line is `preset <name>`, which must come
+    /// before any bindings, or `<key> <action>`, which replaces whatever the
+    /// preset bound that key to. `<key> none` unbinds a key. Blank lines and
+    /// anything after a `#` are ignored:
+    ///
+    /// ```text
+    /// preset vi
+    /// ctrl-s save
+    /// esc none
+    /// ```
+    pub fn parse(text: &str) -> Result<KeyBindings, BindingsError> {
+        let mut bindings = KeyBindings::default();
+        // Keys this file has bound or unbound, and where, to catch it
+        // binding one twice.
+        let mut bound_here: Vec<(Key, Option<Action>, usize)> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let line = line.split('#').next().unwrap();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["preset", name] => {
+                    if !bound_here.is_empty() {
+                        return Err(BindingsError(format!("line {}: presets must come before any bindings", number)));
+                    }
+                    bindings = KeyBindings::preset(name)
+                        .ok_or_else(|| BindingsError(format!("line {}: unknown preset '{}'", number, name)))?;
+                }
+                [key_text, action_name] => {
+                    let key = parse_key(key_text)
+                        .ok_or_else(|| BindingsError(format!("line {}: unknown key '{}'", number, key_text)))?;
+                    let action = match *action_name {
+                        "none" => None,
+                        name => Some(Action::from_name(name)
+                            .ok_or_else(|| BindingsError(format!("line {}: unknown action '{}'", number, name)))?),
+                    };
+                    if let Some(&(_, earlier, earlier_line)) = bound_here.iter().find(|&&(bound, _, _)| bound == key) {
+                        return Err(BindingsError(match earlier {
+                            Some(earlier) => format!("line {}: {} is already bound to {} on line {}", number, key_text, earlier, earlier_line),
+                            None => format!("line {}: {} is already unbound on line {}", number, key_text, earlier_line),
+                        }));
+                    }
+                    bindings.bindings.retain(|&(bound, _)| bound != key);
+                    if let Some(action) = action {
+                        bindings.bindings.push((key, action));
+                    }
+                    bound_here.push((key, action, number));
+                }
+                _ => return Err(BindingsError(format!("line {}: expected '<key> <action>' or 'preset <name>'", number))),
+            }
+        }
+        if bindings.keys_for(Action::Quit).is_empty() {
+            return Err(BindingsError(String::from("nothing is bound to quit")));
+        }
+        Ok(bindings)
+    }
+
+    pub fn read(path: &str) -> Result<KeyBindings, BindingsError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        KeyBindings::parse(&text)
+    }
+
+    /// Where a bindings file is picked up from when none is given.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/flock/keys"))
+    }
+
+    pub fn action_for(&self, key: Key) -> Option<Action> {
+        self.bindings.iter().find(|&&(bound, _)| bound == key).map(|&(_, action)| action)
+    }
+
+    pub fn keys_for(&self, action: Action) -> Vec<Key> {
+        self.bindings.iter().filter(|&&(_, bound)| bound == action).map(|&(key, _)| key).collect()
+    }
+
+    /// A line per bound action, listing its keys.
+    pub fn help_lines(&self) -> Vec<String> {
+        ACTION_NAMES.iter()
+            .filter_map(|&(action, name)| {
+                let keys = self.keys_for(action);
+                if keys.is_empty() {
+                    return None;
+                }
+                let keys: Vec<String> = keys.into_iter().map(key_name).collect();
+                Some(format!("{:<13} {}", name, keys.join(", ")))
+            })
+            .collect()
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings::preset(DEFAULT_PRESET).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use termion::event::Key;
+
+    use input::Action;
+
+    use player::Direction;
+
+    use super::KeyBindings;
+
+    fn error(text: &str) -> String {
+        KeyBindings::parse(text).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn vi_preset_binds_vi_keys() {
+        let bindings = KeyBindings::preset("vi").unwrap();
+        assert_eq!(bindings.action_for(Key::Char('k')), Some(Action::Move(Direction::North)));
+        assert_eq!(bindings.action_for(Key::Char('Y')), Some(Action::Run(Direction::NorthWest)));
+        assert_eq!(bindings.action_for(Key::Char('?')), Some(Action::Help));
+        assert_eq!(bindings.action_for(Key::Char('w')), None);
+    }
+
+    #[test]
+    fn file_bindings_replace_the_preset() {
+        let bindings = KeyBindings::parse("preset vi\nctrl-s save\nk wait\nesc none").unwrap();
+        assert_eq!(bindings.action_for(Key::Ctrl('s')), Some(Action::Save));
+        assert_eq!(bindings.action_for(Key::Char('k')), Some(Action::Wait));
+        assert_eq!(bindings.action_for(Key::Esc), None);
+        assert_eq!(bindings.keys_for(Action::Move(Direction::North)), [Key::Up]);
+    }
+
+    #[test]
+    fn rejects_binding_a_key_twice() {
+        assert_eq!(error("x save\n\nx load"), "line 3: x is already bound to save on line 1");
+        assert_eq!(error("esc none\nesc save"), "line 2: esc is already unbound on line 1");
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        assert_eq!(error("x fly"), "line 1: unknown action 'fly'");
+        assert_eq!(error("hyper-x save"), "line 1: unknown key 'hyper-x'");
+        assert_eq!(error("preset emacs"), "line 1: unknown preset 'emacs'");
+    }
+
+    #[test]
+    fn rejects_misplaced_presets_and_bad_lines() {
+        assert_eq!(error("x save\npreset vi"), "line 2: presets must come before any bindings");
+        assert_eq!(error("esc none\npreset vi"), "line 2: presets must come before any bindings");
+        assert_eq!(error("x save load"), "line 1: expected '<key> <action>' or 'preset <name>'");
+    }
+
+    #[test]
+    fn rejects_leaving_nothing_bound_to_quit() {
+        assert_eq!(error("esc none\nctrl-c none"), "nothing is bound to quit");
+    }
+}
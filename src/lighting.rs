@@ -0,0 +1,70 @@
+/// How the scene is lit at some time of day: a colour multiplier for
+/// everything drawn, and how far from the player anything can be seen.
+#[derive(Debug, Clone, Copy)]
+pub struct Lighting {
+    pub tint: [f32; 3],
+    /// Tiles further than this from the player are too dark to see. `None`
+    /// in broad daylight.
+    pub visible_radius: Option<f32>,
+}
+
+/// Beyond this the light doesn't noticeably limit the view.
+const DAYLIGHT_RADIUS: f32 = 200.0;
+/// Tiles over which the edge of the visible area fades to black.
+const DARKNESS_FADE: f32 = 3.0;
+
+/// Lighting through the day as (time of day, tint, visible radius), starting
+/// at dawn. Times in between are interpolated.
+const KEYFRAMES: [(f32, [f32; 3], f32); 7] = [
+    (0.0, [0.45, 0.45, 0.65], 12.0),
+    (0.1, [1.0, 0.85, 0.75], 40.0),
+    (0.2, [1.0, 1.0, 1.0], DAYLIGHT_RADIUS),
+    (0.6, [1.0, 1.0, 1.0], DAYLIGHT_RADIUS),
+    (0.7, [1.0, 0.7, 0.55], 30.0),
+    (0.8, [0.3, 0.35, 0.55], 10.0),
+    (1.0, [0.45, 0.45, 0.65], 12.0),
+];
+
+impl Lighting {
+    /// Broad daylight, hiding nothing.
+    pub fn daylight() -> Lighting {
+        Lighting {
+            tint: [1.0, 1.0, 1.0],
+            visible_radius: None,
+        }
+    }
+
+    /// Lighting for a time of day, as a fraction of the day from dawn.
+    pub fn at(time_of_day: f32) -> Lighting {
+        let time_of_day = time_of_day.clamp(0.0, 1.0);
+        let next = KEYFRAMES.iter().position(|&(time, _, _)| time >= time_of_day).unwrap_or(KEYFRAMES.len() - 1).max(1);
+        let (start, start_tint, start_radius) = KEYFRAMES[next - 1];
+        let (end, end_tint, end_radius) = KEYFRAMES[next];
+        let t = (time_of_day - start) / (end - start);
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        let radius = mix(start_radius, end_radius);
+        Lighting {
+            tint: [mix(start_tint[0], end_tint[0]), mix(start_tint[1], end_tint[1]), mix(start_tint[2], end_tint[2])],
+            visible_radius: if radius >= DAYLIGHT_RADIUS { None } else { Some(radius) },
+        }
+    }
+
+    /// How much light reaches a point this far from the player, from 0
+    /// (pitch dark) to 1 (fully lit).
+    pub fn brightness_at(&self, distance: f32) -> f32 {
+        match self.visible_radius {
+            Some(radius) => ((radius - distance) / DARKNESS_FADE).clamp(0.0, 1.0),
+            None => 1.0,
+        }
+    }
+
+    /// Applies the light to a colour, at some distance from the player.
+    pub fn light(&self, colour: [u8; 3], distance: f32) -> [u8; 3] {
+        let brightness = self.brightness_at(distance);
+        [
+            (colour[0] as f32 * self.tint[0] * brightness) as u8,
+            (colour[1] as f32 * self.tint[1] * brightness) as u8,
+            (colour[2] as f32 * self.tint[2] * brightness) as u8,
+        ]
+    }
+}
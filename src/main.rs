@@ -3,63 +3,186 @@ extern crate rand;
 extern crate termion;
 extern crate time;
 extern crate specs;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate ron;
+extern crate signal_hook;
+extern crate png;
 
 mod tile;
 mod player;
 mod flock;
 mod renderable;
 mod terminal_renderer;
+mod headless_renderer;
 mod ecs_entities;
 mod ecs_systems;
-
+mod lighting;
+mod cell_buffer;
+mod savegame;
+mod options;
+mod input;
+mod actions;
+mod keybindings;
+mod export;
+mod tile_types;
+mod palette;
+mod theme;
+
+use std::mem;
 use std::thread;
 use std::time as stdtime;
-use std::io::Read;
 use std::sync::{mpsc, Arc, Mutex};
 
-use termion::event::Key;
-use termion::input::TermRead;
-
 use rand::{thread_rng, Rng};
 
-use time::{now, Duration};
+use time::Duration;
 
-use specs::{World, RunNow};
+use specs::World;
 
-use tile::TileMapView;
+use tile::{TileMap, TileMapView};
 
 use player::Player;
 
 use renderable::Renderable;
 
-use terminal_renderer::Renderer;
+use terminal_renderer::{Renderer, HUD_ROWS_ABOVE, HUD_ROWS_BELOW};
+
+use headless_renderer::HeadlessRenderer;
+
+use ecs_entities::Position;
+
+use ecs_systems::{Simulation, GameClock, PlayerPosition, PenCount, EventLog, Hud};
+
+use lighting::Lighting;
+
+use cell_buffer::CellBuffer;
+
+use savegame::SaveGame;
+
+use options::{Options, ViewSize, USAGE};
+
+use input::{Action, InputSource, TermInput, ScriptedInput};
 
-use ecs_entities::Utterance;
+use keybindings::KeyBindings;
 
-use ecs_systems::{UtteranceSystem, GameTimeNow};
+use tile_types::TileTypes;
+
+use palette::ColourDepth;
+
+use theme::Theme;
 
 fn main() {
     // Game setup
-    let mut stdin = std::io::stdin();
-    let map = Arc::new(Mutex::new(flock::generate_tilemap(300, 300)));
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    let save = options.load.as_ref().map(|path| match SaveGame::read(path) {
+        Ok(save) => save,
+        Err(error) => {
+            eprintln!("Couldn't load {}: {}", path, error);
+            std::process::exit(1);
+        }
+    });
+    let mut script = match options.script {
+        Some(ref path) => match ScriptedInput::read(path) {
+            Ok(script) => script,
+            Err(error) => {
+                eprintln!("Couldn't read {}: {}", path, error);
+                std::process::exit(1);
+            }
+        },
+        None => ScriptedInput::default(),
+    };
+    let bindings = match options.keys {
+        Some(ref path) => KeyBindings::read(path).unwrap_or_else(|error| {
+            eprintln!("Couldn't read {}: {}", path, error);
+            std::process::exit(1);
+        }),
+        None => match KeyBindings::default_path() {
+            Some(ref path) if path.exists() => KeyBindings::read(&path.to_string_lossy()).unwrap_or_else(|error| {
+                eprintln!("Couldn't read {}: {}", path.display(), error);
+                std::process::exit(1);
+            }),
+            _ => KeyBindings::default(),
+        },
+    };
+
+    let theme = Theme::load(&options.theme).unwrap_or_else(|error| {
+        eprintln!("Couldn't load the {} theme: {}", options.theme, error);
+        std::process::exit(1);
+    });
+    let mut tile_types = match options.tiles {
+        Some(ref path) => TileTypes::read(path).unwrap_or_else(|error| {
+            eprintln!("Couldn't read {}: {}", path, error);
+            std::process::exit(1);
+        }),
+        None => TileTypes::default(),
+    };
+    if let Err(error) = theme.apply(&mut tile_types) {
+        eprintln!("Couldn't use the {} theme: {}", options.theme, error);
+        std::process::exit(1);
+    }
+    let tile_types = Arc::new(tile_types);
+
+    let map = Arc::new(Mutex::new(match save {
+        Some(ref save) => save.to_tilemap(tile_types).unwrap_or_else(|error| {
+            eprintln!("Couldn't load {}: {}", options.load.as_ref().unwrap(), error);
+            std::process::exit(1);
+        }),
+        None => flock::generate_tilemap(options.map_width, options.map_height, options.seed.unwrap_or_else(|| thread_rng().next_u32()), tile_types),
+    }));
+    if let Some(ref path) = options.export_png {
+        export_png(&options, path, &map.lock().unwrap());
+        return;
+    }
     let player = {
         let map = map.lock().unwrap();
-        Arc::new(Mutex::new(Player {
-            x: map.width / 2,
-            y: map.height / 2,
-            limit_x: map.width - 1,
-            limit_y: map.height - 1,
+        Arc::new(Mutex::new(match save {
+            Some(ref save) => save.to_player(),
+            None => Player::new(map.width / 2, map.height / 2, map.width - 1, map.height - 1),
         }))
     };
 
     // ECS
     let world = {
-        let mut world = World::new();
-        world.register::<Utterance>();
-        world.add_resource(GameTimeNow(now()));
+        let mut world = ecs_systems::new_world();
+        world.add_resource(bindings.clone());
+        world.add_resource(theme);
+        {
+            let player = player.lock().unwrap();
+            world.add_resource(PlayerPosition(Position { x: player.x as f32, y: player.y as f32 }));
+        }
+        match save {
+            Some(save) => save.restore_world(&mut world),
+            None => {
+                let map = map.lock().unwrap();
+                flock::spawn_flock(&mut world, &map, map.width / 2, map.height / 2, 20);
+                flock::spawn_dog(&mut world, &map, map.width / 2, map.height / 2);
+            }
+        }
         Arc::new(Mutex::new(world))
     };
 
+    if options.export_html.is_some() || options.export_ans.is_some() {
+        export_view(&options, &player.lock().unwrap(), &world.lock().unwrap(), &map.lock().unwrap());
+        return;
+    }
+
+    if options.headless {
+        run_headless(&options, map, player, world, &mut script);
+        return;
+    }
+
     // Thread control channels
     let (tx1, rx1) = mpsc::channel();
     let (tx2, rx2) = mpsc::channel();
@@ -69,14 +192,17 @@ fn main() {
     let map_render = map.clone();
     let world_render = world.clone();
     thread::Builder::new().name("render".to_string()).spawn(move || {
-        let frame_sleep = stdtime::Duration::from_millis(64); // ~15 fps
+        let frame_sleep = stdtime::Duration::from_millis(1000 / options.fps as u64);
 
         let mut view = {
             let map = map_render.lock().unwrap();
-            TileMapView::new(&map, 80, 40)
+            match options.view {
+                ViewSize::Fixed(width, height) => TileMapView::new(&map, width, height),
+                ViewSize::FitTerminal => TileMapView::new(&map, 0, 0),
+            }
         };
 
-        let renderer = Renderer::new();
+        let renderer = Renderer::new(options.colours.unwrap_or_else(ColourDepth::detect));
 
         let mut frame_counter: u8 = 0;
         let mut rand: f32 = 0.0;
@@ -98,21 +224,7 @@ fn main() {
             frame_counter += 1;
             frame_counter %= 10;
 
-            {
-                let player = player_render.lock().unwrap();
-                view.centre_on_map_point(player.x, player.y);
-            }
-
-            {
-                let player = player_render.lock().unwrap();
-                let map = map_render.lock().unwrap();
-                renderer.render_map(&map, &view, &player, &rand);
-            }
-
-            {
-                let world = world_render.lock().unwrap();
-                renderer.render_world(&world);
-            }
+            render_frame(&renderer, &mut view, options.view == ViewSize::FitTerminal, &map_render, &player_render, &world_render, rand);
         }
 
         renderer.tear_down();
@@ -123,77 +235,205 @@ fn main() {
 
     // Spawn system thread
     let world_ecs = world.clone();
+    let map_ecs = map.clone();
+    let player_ecs = player.clone();
     thread::Builder::new().name("system".to_string()).spawn(move || {
-        let mut utterance_system = UtteranceSystem;
+        let mut simulation = Simulation::new(map_ecs);
         let frame_sleep = stdtime::Duration::from_millis(16); // ~60 fps
+        let mut ticks = 0;
         loop {
             thread::sleep(frame_sleep);
+            // Take the player's position before the world, never while holding it.
+            let player_position = rest_player(&player_ecs, ticks);
             let mut world = world_ecs.lock().unwrap();
-            {
-                let mut delta = world.write_resource::<GameTimeNow>();
-                *delta = GameTimeNow(now());
+            *world.write_resource::<PlayerPosition>() = player_position;
+            // Run as many fixed ticks as the game clock says this frame is worth.
+            ticks = world.write_resource::<GameClock>().advance(Duration::from_std(frame_sleep).unwrap());
+            for _ in 0..ticks {
+                simulation.tick(&mut world);
+            }
+            if ticks == 0 {
+                // Fences can still go up while the clock stands still.
+                simulation.refresh(&mut world);
             }
-            utterance_system.run_now(&world.res);
-            world.maintain();
         }
     }).unwrap();
 
-    'gameloop: loop {
-        for c in stdin.by_ref().keys() {
-            let mut player = player.lock().unwrap();
-            match c.unwrap() {
-                Key::Up | Key::Char('w') => {
-                    if player.y == 0 {
-                        break;
-                    }
-                    if let Some(tile) = map.lock().unwrap().get_tile(player.x, player.y - 1) {
-                        if flock::tile_is_passable(tile) {
-                            player.go_north();
-                        }
-                    }
-                }
-                Key::Right | Key::Char('d') => {
-                    if player.x == player.limit_x {
-                        break;
-                    }
-                    if let Some(tile) = map.lock().unwrap().get_tile(player.x + 1, player.y) {
-                        if flock::tile_is_passable(tile) {
-                            player.go_east();
-                        }
-                    }
-                }
-                Key::Down | Key::Char('s') => {
-                    if player.y == player.limit_y {
-                        break;
-                    }
-                    if let Some(tile) = map.lock().unwrap().get_tile(player.x, player.y + 1) {
-                        if flock::tile_is_passable(tile) {
-                            player.go_south();
-                        }
-                    }
-                }
-                Key::Left | Key::Char('a') => {
-                    if player.x == 0 {
-                        break;
-                    }
-                    if let Some(tile) = map.lock().unwrap().get_tile(player.x - 1, player.y) {
-                        if flock::tile_is_passable(tile) {
-                            player.go_west();
-                        }
-                    }
-                }
-                Key::Char(' ') => {
-                    let mut world = world.lock().unwrap();
-                    world.create_entity().with(Utterance { text: String::from("Howdy"), dead_at: (now() + Duration::seconds(5)) }).build();
-                }
-                _ => break 'gameloop,
-            }
+    let mut input = TermInput::new(std::io::stdin(), bindings);
+    while let Some(action) = input.next_action() {
+        if action == Action::Quit {
             break;
         }
+        actions::perform(action, &map, &player, &world);
     }
 
     // Now gameloop has stopped, tell render thread to tear down...
     tx1.send(()).unwrap();
     // ...then block until render thread sends confirmation.
     rx2.recv().unwrap();
+
+    println!("World seed: {}", map.lock().unwrap().seed);
+}
+
+/// Writes the map, and its heightmap if asked, to PNG images. Exits on
+/// failure.
+fn export_png(options: &Options, path: &str, map: &TileMap) {
+    let exported = export::write_map_png(map, path, options.scale).and_then(|()| match options.heightmap {
+        Some(ref heightmap_path) => {
+            let heights = flock::generate_heightmap(map.width, map.height, map.seed);
+            export::write_heightmap_png(&heights, map.width, map.height, heightmap_path, options.scale)
+        }
+        None => Ok(()),
+    });
+    if let Err(error) = exported {
+        eprintln!("Couldn't export: {}", error);
+        std::process::exit(1);
+    }
+    println!("World seed: {}", map.seed);
+}
+
+/// Draws a view of the map in daylight, animals and all, and writes it out
+/// as a web page and ANSI art as asked. Fixed views are centred on the
+/// player; otherwise the view is the whole map. Exits on failure.
+fn export_view(options: &Options, player: &Player, world: &World, map: &TileMap) {
+    let (width, height, centre) = match options.view {
+        ViewSize::Fixed(width, height) => (width, height, (player.x, player.y)),
+        ViewSize::FitTerminal => (map.width, map.height, (map.width / 2, map.height / 2)),
+    };
+    let mut view = TileMapView::new(map, width, height);
+    view.centre_on_map_point(centre.0, centre.1);
+    let lighting = Lighting::daylight();
+    let mut buffer = CellBuffer::new(width, height + HUD_ROWS_ABOVE + HUD_ROWS_BELOW);
+    let plain = options.colours == Some(ColourDepth::Monochrome);
+    terminal_renderer::draw_map(&mut buffer, map, &view, player, 0.0, &lighting, plain);
+    terminal_renderer::draw_world(&mut buffer, world, &view, &lighting, plain);
+    // Just the map, without the HUD around it.
+    let frame = buffer.region(0, HUD_ROWS_ABOVE, width, height);
+
+    let mut exported = Ok(());
+    if let Some(ref path) = options.export_html {
+        exported = exported.and_then(|()| export::write_html(&frame, &format!("flock, seed {}", map.seed), path, plain));
+    }
+    if let Some(ref path) = options.export_ans {
+        exported = exported.and_then(|()| export::write_ans(&frame, path, plain));
+    }
+    if let Err(error) = exported {
+        eprintln!("Couldn't export: {}", error);
+        std::process::exit(1);
+    }
+    println!("World seed: {}", map.seed);
+}
+
+/// Lets the player recover from the last ticks run, and says where they
+/// are for the next ones.
+fn rest_player(player: &Mutex<Player>, ticks: u32) -> PlayerPosition {
+    let mut player = player.lock().unwrap();
+    player.rest(ticks);
+    PlayerPosition(Position { x: player.x as f32, y: player.y as f32 })
+}
+
+/// Draws and shows one frame, centred on the player, or on the cursor in
+/// broad daylight while the map is open. Fitted views follow the
+/// renderer's size.
+fn render_frame<R: Renderable>(
+    renderer: &R,
+    view: &mut TileMapView,
+    fit: bool,
+    map: &Mutex<TileMap>,
+    player: &Mutex<Player>,
+    world: &Mutex<World>,
+    rand: f32,
+) {
+    if let Some((width, height)) = renderer.poll_resize() {
+        if fit {
+            view.resize(width, height);
+        }
+    }
+
+    let player_tile = {
+        let player = player.lock().unwrap();
+        (player.x, player.y)
+    };
+
+    let lighting = {
+        let world = world.lock().unwrap();
+        let map_mode = world.read_resource::<Hud>().map_mode;
+        match map_mode {
+            Some(map_mode) => {
+                view.zoom = map_mode.zoom;
+                view.cursor = Some(map_mode.cursor);
+                view.centre_on_map_point(map_mode.cursor.0, map_mode.cursor.1);
+                Lighting::daylight()
+            }
+            None => {
+                view.zoom = 1;
+                view.cursor = None;
+                view.centre_on_map_point(player_tile.0, player_tile.1);
+                Lighting::at(world.read_resource::<GameClock>().time_of_day())
+            }
+        }
+    };
+
+    let seed = {
+        let player = player.lock().unwrap();
+        let map = map.lock().unwrap();
+        view.follow_map(&map);
+        renderer.render_map(&map, view, &player, &rand, &lighting);
+        map.seed
+    };
+
+    {
+        let world = world.lock().unwrap();
+        renderer.render_world(&world, view, &lighting);
+        let export = mem::replace(&mut world.write_resource::<Hud>().export, false);
+        if export {
+            let exported = renderer.export(&format!("flock, seed {}", seed), export::VIEW_HTML_PATH, export::VIEW_ANS_PATH);
+            let now = world.read_resource::<GameClock>().now;
+            world.write_resource::<EventLog>().push(now, match exported {
+                Ok(()) => format!("Exported the view to {} and {}", export::VIEW_HTML_PATH, export::VIEW_ANS_PATH),
+                Err(error) => format!("Couldn't export: {}", error),
+            });
+        }
+    }
+
+    renderer.present();
+}
+
+/// Runs the simulation flat out for a fixed number of ticks, taking one
+/// action a tick from the input until it runs dry, then reports how the
+/// flock got on.
+fn run_headless(options: &Options, map: Arc<Mutex<TileMap>>, player: Arc<Mutex<Player>>, world: Arc<Mutex<World>>, input: &mut dyn InputSource) {
+    let mut simulation = Simulation::new(map.clone());
+    for _ in 0..options.ticks {
+        match input.next_action() {
+            Some(Action::Quit) => break,
+            Some(action) => actions::perform(action, &map, &player, &world),
+            None => {}
+        }
+        let player_position = rest_player(&player, 1);
+        let mut world = world.lock().unwrap();
+        *world.write_resource::<PlayerPosition>() = player_position;
+        simulation.tick(&mut world);
+    }
+
+    if options.snapshot {
+        let (width, height) = match options.view {
+            ViewSize::Fixed(width, height) => (width, height),
+            ViewSize::FitTerminal => (80, 40),
+        };
+        let renderer = HeadlessRenderer::new(width, height, options.colours == Some(ColourDepth::Monochrome));
+        let mut view = TileMapView::new(&map.lock().unwrap(), width, height);
+        render_frame(&renderer, &mut view, false, &map, &player, &world, 0.0);
+        print!("{}", renderer.frame().text());
+    }
+
+    let world = world.lock().unwrap();
+
+    println!("World seed: {}", map.lock().unwrap().seed);
+    let clock = world.read_resource::<GameClock>();
+    let pen_count = world.read_resource::<PenCount>();
+    println!("Day {} {}: {}/{} sheep penned", clock.day_number(), clock.day_phase(), pen_count.penned, pen_count.total);
+    for entry in &world.read_resource::<EventLog>().0 {
+        println!("{}", entry);
+    }
 }
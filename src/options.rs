@@ -0,0 +1,259 @@
+use std::fmt;
+
+use palette::ColourDepth;
+
+use theme::DEFAULT_THEME;
+
+pub const USAGE: &str = "\
+Usage: flock [options] [seed]
+       flock export-png <file> [options] [seed]
+       flock export-html <file> [options] [seed]
+       flock export-ans <file> [options] [seed]
+
+Exports write the map to a file and exit, without a terminal. Export-png
+draws the generated map, one colour per tile. Export-html and export-ans
+draw the map as it looks in the game, in daylight, either whole or as a
+--view sized view around the player.
+
+Options:
+    --width <tiles>      Map width (default 300)
+    --height <tiles>     Map height (default 300)
+    --seed <number>      World seed; random if not given
+    --view <WxH|fit>     Viewport size in tiles, or fit the terminal (default fit)
+    --fps <number>       Frames drawn per second, up to 1000 (default 15)
+    --load <file>        Carry on from a save file
+    --keys <file>        Key bindings file (default ~/.config/flock/keys if it exists)
+    --tiles <file>       Tile types file, instead of the built-in ones
+    --theme <name|file>  Colour theme: default, high-contrast, colour-blind or a theme file
+    --colours <depth>    Colours the terminal can show: truecolour, 256, 16 or none,
+                         which draws each kind of tile as its own plain glyph and
+                         animals as letters (default from NO_COLOR, COLORTERM and TERM)
+    --headless           Run the simulation without a terminal
+    --script <file>      Play the actions in a script, one per tick (implies --headless)
+    --ticks <number>     Ticks to simulate when headless (default 3600)
+    --snapshot           Print the final frame as text when headless
+    --scale <pixels>     Pixels across each tile when exporting (default 1)
+    --heightmap <file>   Also export the land height as a greyscale image
+    --help               Show this message";
+
+/// Any faster and frames would be under a millisecond apart, which is as
+/// finely as the render loop sleeps.
+const MAX_FPS: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViewSize {
+    Fixed(usize, usize),
+    FitTerminal,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub map_width: usize,
+    pub map_height: usize,
+    pub seed: Option<u32>,
+    pub view: ViewSize,
+    pub fps: u32,
+    pub load: Option<String>,
+    pub keys: Option<String>,
+    pub tiles: Option<String>,
+    pub theme: String,
+    pub colours: Option<ColourDepth>,
+    pub headless: bool,
+    pub script: Option<String>,
+    pub ticks: u64,
+    pub snapshot: bool,
+    pub export_png: Option<String>,
+    pub scale: usize,
+    pub heightmap: Option<String>,
+    pub export_html: Option<String>,
+    pub export_ans: Option<String>,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            map_width: 300,
+            map_height: 300,
+            seed: None,
+            view: ViewSize::FitTerminal,
+            fps: 15,
+            load: None,
+            keys: None,
+            tiles: None,
+            theme: String::from(DEFAULT_THEME),
+            colours: None,
+            headless: false,
+            script: None,
+            ticks: 3600,
+            snapshot: false,
+            export_png: None,
+            scale: 1,
+            heightmap: None,
+            export_html: None,
+            export_ans: None,
+            help: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OptionsError(String);
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn parse_number<T: ::std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, OptionsError> {
+    let value = value.ok_or_else(|| OptionsError(format!("{} needs a value", option)))?;
+    value.parse().map_err(|_| OptionsError(format!("{} expects a number, got '{}'", option, value)))
+}
+
+fn parse_view(value: Option<String>) -> Result<ViewSize, OptionsError> {
+    let value = value.ok_or_else(|| OptionsError(String::from("--view needs a value")))?;
+    if value == "fit" {
+        return Ok(ViewSize::FitTerminal);
+    }
+    let mut parts = value.splitn(2, 'x');
+    match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+        (Some(Ok(width)), Some(Ok(height))) if width > 0 && height > 0 => Ok(ViewSize::Fixed(width, height)),
+        _ => Err(OptionsError(format!("--view expects WxH or 'fit', got '{}'", value))),
+    }
+}
+
+fn parse_colours(value: Option<String>) -> Result<ColourDepth, OptionsError> {
+    let value = value.ok_or_else(|| OptionsError(String::from("--colours needs a value")))?;
+    ColourDepth::from_name(&value)
+        .ok_or_else(|| OptionsError(format!("--colours expects truecolour, 256, 16 or none, got '{}'", value)))
+}
+
+impl Options {
+    /// Parses command-line arguments, not including the program name.
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, OptionsError> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--width" => options.map_width = parse_number(&arg, args.next())?,
+                "--height" => options.map_height = parse_number(&arg, args.next())?,
+                "--seed" => options.seed = Some(parse_number(&arg, args.next())?),
+                "--view" => options.view = parse_view(args.next())?,
+                "--fps" => options.fps = parse_number(&arg, args.next())?,
+                "--load" => options.load = Some(args.next().ok_or_else(|| OptionsError(String::from("--load needs a save file")))?),
+                "--keys" => options.keys = Some(args.next().ok_or_else(|| OptionsError(String::from("--keys needs a bindings file")))?),
+                "--tiles" => options.tiles = Some(args.next().ok_or_else(|| OptionsError(String::from("--tiles needs a tile types file")))?),
+                "--theme" => options.theme = args.next().ok_or_else(|| OptionsError(String::from("--theme needs a theme name or file")))?,
+                "--colours" => options.colours = Some(parse_colours(args.next())?),
+                "--headless" => options.headless = true,
+                "--script" => {
+                    options.script = Some(args.next().ok_or_else(|| OptionsError(String::from("--script needs a script file")))?);
+                    options.headless = true;
+                }
+                "--ticks" => options.ticks = parse_number(&arg, args.next())?,
+                "--snapshot" => options.snapshot = true,
+                "export-png" => options.export_png = Some(args.next().ok_or_else(|| OptionsError(String::from("export-png needs an image file")))?),
+                "export-html" => options.export_html = Some(args.next().ok_or_else(|| OptionsError(String::from("export-html needs a web page file")))?),
+                "export-ans" => options.export_ans = Some(args.next().ok_or_else(|| OptionsError(String::from("export-ans needs a text file")))?),
+                "--scale" => options.scale = parse_number(&arg, args.next())?,
+                "--heightmap" => options.heightmap = Some(args.next().ok_or_else(|| OptionsError(String::from("--heightmap needs an image file")))?),
+                "--help" | "-h" => options.help = true,
+                // A bare number is a seed, as before there were options.
+                _ if !arg.starts_with('-') => options.seed = Some(parse_number("seed", Some(arg))?),
+                _ => return Err(OptionsError(format!("Unknown option '{}'", arg))),
+            }
+        }
+        if options.map_width < 2 || options.map_height < 2 {
+            return Err(OptionsError(String::from("The map must be at least 2x2")));
+        }
+        if options.scale == 0 {
+            return Err(OptionsError(String::from("--scale must be above 0")));
+        }
+        if options.heightmap.is_some() && options.export_png.is_none() {
+            return Err(OptionsError(String::from("--heightmap only works with export-png")));
+        }
+        if options.export_png.is_some() && (options.export_html.is_some() || options.export_ans.is_some()) {
+            return Err(OptionsError(String::from("export-png can't be combined with export-html or export-ans")));
+        }
+        if options.snapshot && !options.headless {
+            return Err(OptionsError(String::from("--snapshot only works with --headless or --script")));
+        }
+        if options.fps == 0 || options.fps > MAX_FPS {
+            return Err(OptionsError(format!("--fps must be from 1 to {}", MAX_FPS)));
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Options, ViewSize};
+
+    fn parse(args: &str) -> Options {
+        Options::parse(args.split_whitespace().map(String::from)).unwrap()
+    }
+
+    fn error(args: &str) -> String {
+        Options::parse(args.split_whitespace().map(String::from)).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn html_and_ans_exports_combine() {
+        let options = parse("export-html a.html export-ans a.ans");
+        assert_eq!(options.export_html, Some(String::from("a.html")));
+        assert_eq!(options.export_ans, Some(String::from("a.ans")));
+    }
+
+    #[test]
+    fn rejects_png_export_with_other_exports() {
+        assert_eq!(error("export-png a.png export-html a.html"), "export-png can't be combined with export-html or export-ans");
+        assert_eq!(error("export-ans a.ans export-png a.png"), "export-png can't be combined with export-html or export-ans");
+    }
+
+    #[test]
+    fn snapshots_need_a_headless_run() {
+        assert_eq!(error("--snapshot"), "--snapshot only works with --headless or --script");
+        assert!(parse("--snapshot --headless").snapshot);
+        assert!(parse("--snapshot --script moves.txt").snapshot);
+    }
+
+    #[test]
+    fn parses_view_sizes() {
+        assert_eq!(parse("--view 40x20").view, ViewSize::Fixed(40, 20));
+        assert_eq!(parse("--view fit").view, ViewSize::FitTerminal);
+        assert_eq!(parse("").view, ViewSize::FitTerminal);
+        assert_eq!(error("--view 40"), "--view expects WxH or 'fit', got '40'");
+        assert_eq!(error("--view 0x20"), "--view expects WxH or 'fit', got '0x20'");
+        assert_eq!(error("--view"), "--view needs a value");
+    }
+
+    #[test]
+    fn limits_fps() {
+        assert_eq!(parse("--fps 1").fps, 1);
+        assert_eq!(parse("--fps 1000").fps, 1000);
+        assert_eq!(error("--fps 0"), "--fps must be from 1 to 1000");
+        assert_eq!(error("--fps 1001"), "--fps must be from 1 to 1000");
+        assert_eq!(error("--fps fast"), "--fps expects a number, got 'fast'");
+    }
+
+    #[test]
+    fn maps_are_at_least_2x2() {
+        assert_eq!(parse("--width 2 --height 2").map_width, 2);
+        assert_eq!(error("--width 1"), "The map must be at least 2x2");
+        assert_eq!(error("--height 0"), "The map must be at least 2x2");
+    }
+
+    #[test]
+    fn heightmaps_need_a_png_export() {
+        assert_eq!(error("--heightmap h.png"), "--heightmap only works with export-png");
+        assert_eq!(parse("export-png a.png --heightmap h.png").heightmap, Some(String::from("h.png")));
+    }
+
+    #[test]
+    fn bare_numbers_are_seeds() {
+        assert_eq!(parse("42").seed, Some(42));
+        assert_eq!(parse("--seed 7").seed, Some(7));
+        assert_eq!(parse("").seed, None);
+        assert_eq!(error("-42"), "Unknown option '-42'");
+    }
+}
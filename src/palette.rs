@@ -0,0 +1,194 @@
+use std::env;
+
+/// How many colours a terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColourDepth {
+    TrueColour,
+    Ansi256,
+    Ansi16,
+    /// No colour at all, for plain terminals and screen readers. The game
+    /// draws plain glyphs instead.
+    Monochrome,
+}
+
+/// A colour as it's sent to the terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TermColour {
+    Rgb([u8; 3]),
+    /// A colour from the 256-colour palette.
+    Ansi256(u8),
+    /// One of the 16 basic colours; the top eight are the bright ones.
+    Ansi16(u8),
+    /// Whatever the terminal's own colour is.
+    Default,
+}
+
+/// The levels of each channel in the 256-colour palette's colour cube.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    (0..3).map(|channel| (a[channel] as i32 - b[channel] as i32).pow(2) as u32).sum()
+}
+
+/// The nearest colour in the 256-colour palette's colour cube or grey
+/// ramp; the first 16 colours vary too much between terminals to use.
+fn nearest_ansi256(colour: [u8; 3]) -> u8 {
+    let level = |value: u8| {
+        (0..CUBE_LEVELS.len()).min_by_key(|&index| (CUBE_LEVELS[index] as i32 - value as i32).abs()).unwrap()
+    };
+    let (r, g, b) = (level(colour[0]), level(colour[1]), level(colour[2]));
+    let cube = [CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]];
+    let average = (colour.iter().map(|&channel| channel as u32).sum::<u32>() / 3) as u8;
+    let grey_index = (average.saturating_sub(8) / 10).min(23);
+    let grey_value = 8 + grey_index * 10;
+    if distance(colour, [grey_value; 3]) < distance(colour, cube) {
+        232 + grey_index
+    } else {
+        16 + 36 * r as u8 + 6 * g as u8 + b as u8
+    }
+}
+
+/// The nearest of the 16 basic colours by hue, then brightness. Greyish
+/// colours go to the nearest grey. Going by distance alone would turn
+/// most of the island's muted colours grey too.
+fn nearest_ansi16(colour: [u8; 3]) -> u8 {
+    let [r, g, b] = [colour[0] as f32, colour[1] as f32, colour[2] as f32];
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    if max == 0.0 || (max - min) / max < 0.2 {
+        return if max < 64.0 { 0 } else if max < 160.0 { 8 } else if max < 220.0 { 7 } else { 15 };
+    }
+    let hue = if max == r {
+        60.0 * ((g - b) / (max - min))
+    } else if max == g {
+        60.0 * ((b - r) / (max - min) + 2.0)
+    } else {
+        60.0 * ((r - g) / (max - min) + 4.0)
+    };
+    // Basic colours in order round the colour wheel, from red.
+    let basic = [1, 3, 2, 6, 4, 5][(((hue + 390.0) % 360.0) / 60.0) as usize];
+    if max > 191.0 { basic + 8 } else { basic }
+}
+
+impl ColourDepth {
+    /// Works out what the terminal supports from the environment, the way
+    /// most terminal programs do.
+    pub fn detect() -> ColourDepth {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if env::var("NO_COLOR").map(|value| !value.is_empty()).unwrap_or(false) || term == "dumb" {
+            ColourDepth::Monochrome
+        } else if colorterm == "truecolor" || colorterm == "24bit" {
+            ColourDepth::TrueColour
+        } else if term.contains("256color") {
+            ColourDepth::Ansi256
+        } else {
+            ColourDepth::Ansi16
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ColourDepth> {
+        match name {
+            "truecolour" | "truecolor" | "24bit" => Some(ColourDepth::TrueColour),
+            "256" => Some(ColourDepth::Ansi256),
+            "16" => Some(ColourDepth::Ansi16),
+            "none" => Some(ColourDepth::Monochrome),
+            _ => None,
+        }
+    }
+
+    /// The closest colour to this one the terminal can show.
+    pub fn reduce(self, colour: [u8; 3]) -> TermColour {
+        match self {
+            ColourDepth::TrueColour => TermColour::Rgb(colour),
+            ColourDepth::Ansi256 => TermColour::Ansi256(nearest_ansi256(colour)),
+            ColourDepth::Ansi16 => TermColour::Ansi16(nearest_ansi16(colour)),
+            ColourDepth::Monochrome => TermColour::Default,
+        }
+    }
+}
+
+impl TermColour {
+    /// The other brightness of a basic colour, so text can be told apart
+    /// from a background that reduced to the same colour. Others are left
+    /// alone.
+    pub fn contrast(self) -> TermColour {
+        match self {
+            TermColour::Ansi16(index) if index < 8 => TermColour::Ansi16(index + 8),
+            TermColour::Ansi16(index) => TermColour::Ansi16(index - 8),
+            other => other,
+        }
+    }
+
+    /// The escape code setting the foreground to this colour.
+    pub fn fg(self) -> String {
+        match self {
+            TermColour::Rgb([r, g, b]) => format!("\x1b[38;2;{};{};{}m", r, g, b),
+            TermColour::Ansi256(index) => format!("\x1b[38;5;{}m", index),
+            TermColour::Ansi16(index) if index < 8 => format!("\x1b[{}m", 30 + index),
+            TermColour::Ansi16(index) => format!("\x1b[{}m", 90 + index - 8),
+            TermColour::Default => String::new(),
+        }
+    }
+
+    /// The escape code setting the background to this colour.
+    pub fn bg(self) -> String {
+        match self {
+            TermColour::Rgb([r, g, b]) => format!("\x1b[48;2;{};{};{}m", r, g, b),
+            TermColour::Ansi256(index) => format!("\x1b[48;5;{}m", index),
+            TermColour::Ansi16(index) if index < 8 => format!("\x1b[{}m", 40 + index),
+            TermColour::Ansi16(index) => format!("\x1b[{}m", 100 + index - 8),
+            TermColour::Default => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{nearest_ansi16, nearest_ansi256};
+
+    #[test]
+    fn greys_go_to_the_grey_ramp() {
+        assert_eq!(nearest_ansi256([128, 128, 128]), 244);
+        assert_eq!(nearest_ansi256([30, 32, 28]), 234);
+        assert_eq!(nearest_ansi256([238, 238, 238]), 255);
+    }
+
+    #[test]
+    fn colours_and_cube_greys_go_to_the_cube() {
+        assert_eq!(nearest_ansi256([0, 0, 0]), 16);
+        assert_eq!(nearest_ansi256([255, 255, 255]), 231);
+        assert_eq!(nearest_ansi256([255, 0, 0]), 196);
+        assert_eq!(nearest_ansi256([95, 135, 0]), 64);
+        assert_eq!(nearest_ansi256([100, 130, 10]), 64);
+    }
+
+    #[test]
+    fn colours_go_to_their_hue() {
+        assert_eq!(nearest_ansi16([150, 0, 0]), 1);
+        assert_eq!(nearest_ansi16([150, 140, 20]), 3);
+        assert_eq!(nearest_ansi16([30, 120, 40]), 2);
+        assert_eq!(nearest_ansi16([0, 120, 120]), 6);
+        assert_eq!(nearest_ansi16([20, 40, 150]), 4);
+        assert_eq!(nearest_ansi16([150, 0, 150]), 5);
+        // Reds either side of pure red.
+        assert_eq!(nearest_ansi16([150, 0, 30]), 1);
+        assert_eq!(nearest_ansi16([150, 30, 0]), 1);
+    }
+
+    #[test]
+    fn bright_colours_go_to_the_bright_half() {
+        assert_eq!(nearest_ansi16([230, 20, 20]), 9);
+        assert_eq!(nearest_ansi16([0, 200, 0]), 10);
+        assert_eq!(nearest_ansi16([60, 60, 255]), 12);
+    }
+
+    #[test]
+    fn greyish_colours_go_to_grey() {
+        assert_eq!(nearest_ansi16([0, 0, 0]), 0);
+        assert_eq!(nearest_ansi16([40, 44, 42]), 0);
+        assert_eq!(nearest_ansi16([120, 125, 130]), 8);
+        assert_eq!(nearest_ansi16([190, 190, 180]), 7);
+        assert_eq!(nearest_ansi16([250, 250, 250]), 15);
+    }
+}
@@ -1,24 +1,122 @@
+use std::f32::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl Direction {
+    /// The change in x and y of one step this way.
+    pub fn offset(self) -> (isize, isize) {
+        match self {
+            Direction::North => (0, -1),
+            Direction::NorthEast => (1, -1),
+            Direction::East => (1, 0),
+            Direction::SouthEast => (1, 1),
+            Direction::South => (0, 1),
+            Direction::SouthWest => (-1, 1),
+            Direction::West => (-1, 0),
+            Direction::NorthWest => (-1, -1),
+        }
+    }
+
+    /// The nearest of the eight directions to a change in x and y.
+    pub fn towards(dx: f32, dy: f32) -> Direction {
+        // Anticlockwise from east, as y grows southwards.
+        const AROUND: [Direction; 8] = [
+            Direction::East,
+            Direction::NorthEast,
+            Direction::North,
+            Direction::NorthWest,
+            Direction::West,
+            Direction::SouthWest,
+            Direction::South,
+            Direction::SouthEast,
+        ];
+        let eighths = ((-dy).atan2(dx) / (PI / 4.0)).round() as isize;
+        AROUND[eighths.rem_euclid(8) as usize]
+    }
+
+    /// The direction as a compass point, for telling the player.
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::North => "north",
+            Direction::NorthEast => "north-east",
+            Direction::East => "east",
+            Direction::SouthEast => "south-east",
+            Direction::South => "south",
+            Direction::SouthWest => "south-west",
+            Direction::West => "west",
+            Direction::NorthWest => "north-west",
+        }
+    }
+}
+
+/// Stamina when fully rested.
+pub const MAX_STAMINA: f32 = 100.0;
+/// Stamina spent on each tile run.
+pub const RUN_STAMINA_PER_TILE: f32 = 8.0;
+/// Tiles covered by one press of a run key, stamina allowing.
+pub const RUN_TILES: usize = 3;
+/// Stamina regained each tick, about ten a second.
+const STAMINA_PER_TICK: f32 = 1.0 / 6.0;
+
 pub struct Player {
     pub x: usize,
     pub y: usize,
     pub limit_x: usize,
     pub limit_y: usize,
+    pub facing: Direction,
+    pub stamina: f32,
 }
 
 impl Player {
-    pub fn go_north(&mut self) {
-        self.y -= 1;
+    /// A rested player standing on a tile, facing south.
+    pub fn new(x: usize, y: usize, limit_x: usize, limit_y: usize) -> Player {
+        Player {
+            x,
+            y,
+            limit_x,
+            limit_y,
+            facing: Direction::South,
+            stamina: MAX_STAMINA,
+        }
+    }
+
+    /// The tile one step away in a direction, unless that's off the map.
+    pub fn neighbour(&self, direction: Direction) -> Option<(usize, usize)> {
+        let (dx, dy) = direction.offset();
+        let x = self.x as isize + dx;
+        let y = self.y as isize + dy;
+        if x < 0 || y < 0 || x > self.limit_x as isize || y > self.limit_y as isize {
+            return None;
+        }
+        Some((x as usize, y as usize))
+    }
+
+    /// The tile directly in front of the player, unless that's off the map.
+    pub fn facing_tile(&self) -> Option<(usize, usize)> {
+        self.neighbour(self.facing)
     }
 
-    pub fn go_east(&mut self) {
-        self.x += 1;
+    pub fn can_run(&self) -> bool {
+        self.stamina >= RUN_STAMINA_PER_TILE
     }
 
-    pub fn go_south(&mut self) {
-        self.y += 1;
+    /// Spends the stamina for running a tile.
+    pub fn tire(&mut self) {
+        self.stamina = (self.stamina - RUN_STAMINA_PER_TILE).max(0.0);
     }
 
-    pub fn go_west(&mut self) {
-        self.x -= 1;
+    /// Recovers stamina over some ticks of game time.
+    pub fn rest(&mut self, ticks: u32) {
+        self.stamina = (self.stamina + ticks as f32 * STAMINA_PER_TICK).min(MAX_STAMINA);
     }
 }
@@ -5,9 +5,21 @@ use tile::TileMapView;
 
 use player::Player;
 
+use lighting::Lighting;
+
+use export::ExportError;
+
 pub trait Renderable {
     fn set_up(&self);
-    fn render_map(&self, &TileMap, &TileMapView, &Player, &f32);
-    fn render_world(&self, &World);
+    /// The view size that fits the output, if it has changed since the last
+    /// call. The first call always returns the starting size.
+    fn poll_resize(&self) -> Option<(usize, usize)>;
+    fn render_map(&self, map: &TileMap, map_view: &TileMapView, player: &Player, rand: &f32, lighting: &Lighting);
+    fn render_world(&self, world: &World, map_view: &TileMapView, lighting: &Lighting);
+    /// Writes the view rendered since the last `present`, without the HUD,
+    /// as a web page and ANSI art.
+    fn export(&self, title: &str, html_path: &str, ans_path: &str) -> Result<(), ExportError>;
+    /// Shows everything rendered since the last call.
+    fn present(&self);
     fn tear_down(&self);
 }
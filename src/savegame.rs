@@ -0,0 +1,267 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+
+use ron;
+
+use specs::{World, Join};
+
+use std::sync::Arc;
+
+use tile::{Tile, TileMap, TileStyle};
+
+use tile_types::TileTypes;
+
+use player::{Player, Direction, MAX_STAMINA};
+
+use ecs_entities::{Utterance, Position, Velocity, Heading, Sheep, Dog, Threat, Wolf, Avatar};
+
+use ecs_systems::{GameClock, DogCommand, EventLog, LogEntry, Reseed, Weather};
+
+/// Bumped whenever the save format changes in a way old saves can't be
+/// read back into.
+pub const SAVE_VERSION: u32 = 4;
+
+pub const DEFAULT_SAVE_PATH: &str = "flock-save.ron";
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Format(String),
+    Version(u32),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveError::Io(ref error) => write!(f, "{}", error),
+            SaveError::Format(ref error) => write!(f, "bad save file: {}", error),
+            SaveError::Version(version) => write!(f, "save is version {}, expected {}", version, SAVE_VERSION),
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(error: io::Error) -> SaveError {
+        SaveError::Io(error)
+    }
+}
+
+/// Serialises a `time::Duration` as whole milliseconds.
+pub mod milliseconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(duration.num_milliseconds())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        i64::deserialize(deserializer).map(Duration::milliseconds)
+    }
+}
+
+/// The map's tiles, row by row as in `TileMap`. Tile coordinates follow
+/// from the index, so only styles and offsets are kept. Styles index into
+/// the ids of the tile types the map was saved with, so a save still loads
+/// if the tiles file is reordered.
+#[derive(Serialize, Deserialize)]
+struct SavedMap {
+    width: usize,
+    height: usize,
+    seed: u32,
+    types: Vec<String>,
+    styles: Vec<u16>,
+    offsets: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedPlayer {
+    x: usize,
+    y: usize,
+    facing: Direction,
+    stamina: f32,
+}
+
+/// Every component an entity can have; absent ones are `None` or `false`.
+#[derive(Serialize, Deserialize, Default)]
+struct SavedEntity {
+    position: Option<Position>,
+    velocity: Option<Velocity>,
+    heading: Option<Heading>,
+    sheep: bool,
+    dog: Option<Dog>,
+    threat: bool,
+    avatar: bool,
+    wolf: Option<Wolf>,
+    utterance: Option<Utterance>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveGame {
+    version: u32,
+    map: SavedMap,
+    player: SavedPlayer,
+    clock: GameClock,
+    weather: Weather,
+    dog_command: DogCommand,
+    log: Vec<LogEntry>,
+    entities: Vec<SavedEntity>,
+}
+
+impl SaveGame {
+    pub fn capture(map: &TileMap, player: &Player, world: &World) -> SaveGame {
+        let positions = world.read::<Position>();
+        let velocities = world.read::<Velocity>();
+        let headings = world.read::<Heading>();
+        let sheep = world.read::<Sheep>();
+        let dogs = world.read::<Dog>();
+        let threats = world.read::<Threat>();
+        let avatars = world.read::<Avatar>();
+        let wolves = world.read::<Wolf>();
+        let utterances = world.read::<Utterance>();
+        let entities = (&*world.entities()).join().map(|entity| SavedEntity {
+            position: positions.get(entity).cloned(),
+            velocity: velocities.get(entity).cloned(),
+            heading: headings.get(entity).cloned(),
+            sheep: sheep.get(entity).is_some(),
+            dog: dogs.get(entity).cloned(),
+            threat: threats.get(entity).is_some(),
+            avatar: avatars.get(entity).is_some(),
+            wolf: wolves.get(entity).cloned(),
+            utterance: utterances.get(entity).cloned(),
+        }).collect();
+
+        SaveGame {
+            version: SAVE_VERSION,
+            map: SavedMap {
+                width: map.width,
+                height: map.height,
+                seed: map.seed,
+                types: map.types.ids(),
+                styles: map.tiles.iter().map(|tile| tile.style.0).collect(),
+                offsets: map.tiles.iter().map(|tile| tile.rand_offset).collect(),
+            },
+            player: SavedPlayer {
+                x: player.x,
+                y: player.y,
+                facing: player.facing,
+                stamina: player.stamina,
+            },
+            clock: world.read_resource::<GameClock>().clone(),
+            weather: *world.read_resource::<Weather>(),
+            dog_command: *world.read_resource::<DogCommand>(),
+            log: world.read_resource::<EventLog>().0.clone(),
+            entities,
+        }
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), SaveError> {
+        let text = ron::to_string(self).map_err(|error| SaveError::Format(error.to_string()))?;
+        File::create(path)?.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn read(path: &str) -> Result<SaveGame, SaveError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        let save: SaveGame = ron::from_str(&text).map_err(|error| SaveError::Format(error.to_string()))?;
+        if save.version != SAVE_VERSION {
+            return Err(SaveError::Version(save.version));
+        }
+        let (width, height) = (save.map.width, save.map.height);
+        if width < 2 || height < 2 {
+            return Err(SaveError::Format(format!("{}x{} map is too small", width, height)));
+        }
+        if save.map.styles.len() != save.map.width * save.map.height || save.map.offsets.len() != save.map.styles.len() {
+            return Err(SaveError::Format(String::from("tile count doesn't match map size")));
+        }
+        if save.map.styles.iter().any(|&style| style as usize >= save.map.types.len()) {
+            return Err(SaveError::Format(String::from("tile of an unlisted type")));
+        }
+        if save.player.x >= width || save.player.y >= height {
+            return Err(SaveError::Format(String::from("player is off the map")));
+        }
+        let on_map = |position: &Position| {
+            position.x >= 0.0 && position.x < width as f32 && position.y >= 0.0 && position.y < height as f32
+        };
+        if save.entities.iter().filter_map(|entity| entity.position.as_ref()).any(|position| !on_map(position)) {
+            return Err(SaveError::Format(String::from("entity is off the map")));
+        }
+        Ok(save)
+    }
+
+    /// The saved map, with its tiles matched up to the given tile types by
+    /// id. Fails if any of them is missing.
+    pub fn to_tilemap(&self, types: Arc<TileTypes>) -> Result<TileMap, SaveError> {
+        let saved_styles = self.map.types.iter()
+            .map(|id| types.find(id).ok_or_else(|| SaveError::Format(format!("no '{}' tile type", id))))
+            .collect::<Result<Vec<TileStyle>, SaveError>>()?;
+        let mut map = TileMap::new(self.map.width, self.map.height, self.map.seed, types);
+        let width = self.map.width;
+        let (styles, offsets) = (&self.map.styles, &self.map.offsets);
+        map.fill_tiles(|x, y| Tile {
+            x,
+            y,
+            style: saved_styles[styles[y * width + x] as usize],
+            rand_offset: offsets[y * width + x],
+        });
+        Ok(map)
+    }
+
+    pub fn to_player(&self) -> Player {
+        Player {
+            x: self.player.x,
+            y: self.player.y,
+            limit_x: self.map.width - 1,
+            limit_y: self.map.height - 1,
+            facing: self.player.facing,
+            // Hand-edited saves could hold any amount.
+            stamina: self.player.stamina.clamp(0.0, MAX_STAMINA),
+        }
+    }
+
+    /// Replaces every entity and game resource in the world with the saved
+    /// ones. Components must already be registered.
+    pub fn restore_world(self, world: &mut World) {
+        world.maintain();
+        world.delete_all();
+        *world.write_resource::<GameClock>() = self.clock;
+        *world.write_resource::<Reseed>() = Reseed(Some(self.map.seed));
+        *world.write_resource::<Weather>() = self.weather;
+        *world.write_resource::<DogCommand>() = self.dog_command;
+        *world.write_resource::<EventLog>() = EventLog(self.log);
+        for saved in self.entities {
+            let mut entity = world.create_entity();
+            if let Some(position) = saved.position {
+                entity = entity.with(position);
+            }
+            if let Some(velocity) = saved.velocity {
+                entity = entity.with(velocity);
+            }
+            if let Some(heading) = saved.heading {
+                entity = entity.with(heading);
+            }
+            if saved.sheep {
+                entity = entity.with(Sheep);
+            }
+            if let Some(dog) = saved.dog {
+                entity = entity.with(dog);
+            }
+            if saved.threat {
+                entity = entity.with(Threat);
+            }
+            if saved.avatar {
+                entity = entity.with(Avatar);
+            }
+            if let Some(wolf) = saved.wolf {
+                entity = entity.with(wolf);
+            }
+            if let Some(utterance) = saved.utterance {
+                entity = entity.with(utterance);
+            }
+            entity.build();
+        }
+    }
+}
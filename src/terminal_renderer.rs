@@ -1,167 +1,600 @@
 use std;
 use std::io::Write;
 use std::cell::RefCell;
+use std::cmp;
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use signal_hook;
+use signal_hook::consts::SIGWINCH;
+
+use termion;
 use termion::raw::IntoRawMode;
 use termion::raw::RawTerminal;
-use termion::{clear, color, cursor, style};
+use termion::{clear, cursor, style};
 
 use specs::{World, Join};
 
 use tile::TileMap;
 use tile::TileMapView;
-use tile::TileStyle;
+use tile::Coord;
+
+use tile_types::TileType;
 
-use player::Player;
+use player::{Player, MAX_STAMINA};
 
 use renderable::Renderable;
 
-use ecs_entities::Utterance;
+use ecs_entities::{Utterance, Position, Sheep, Dog, Wolf};
+
+use ecs_systems::{DogCommand, PenCount, EventLog, GameClock, PlayerPosition, Weather, Hud, Minimap};
+
+use input::Action;
+
+use keybindings::{key_name, KeyBindings};
+
+use lighting::Lighting;
+
+use cell_buffer::{Cell, CellBuffer};
+
+use export::{self, ExportError};
+
+use palette::ColourDepth;
+
+use theme::{Colours, Theme};
+
+/// Screen rows above the map, for the status line.
+pub const HUD_ROWS_ABOVE: usize = 1;
+/// Lines of the event log shown under the map.
+const LOG_ROWS: usize = 4;
+/// Screen rows below the map, for the event log and its header.
+pub const HUD_ROWS_BELOW: usize = 1 + LOG_ROWS;
+/// Where the game status starts on the status line, after the map details.
+const STATUS_COLUMN: usize = 31;
+
+const STAMINA_BAR_WIDTH: usize = 10;
+
+/// Sheep in one minimap cell to count as a cluster.
+const MINIMAP_CLUSTER: usize = 3;
+
+/// Widest a line of speech gets before wrapping.
+const BUBBLE_TEXT_WIDTH: usize = 24;
+/// Game time over which speech fades before it's gone.
+const BUBBLE_FADE_MILLISECONDS: i64 = 1500;
+
+const WHITE: [u8; 3] = [255, 255, 255];
+const BLACK: [u8; 3] = [0, 0, 0];
+const GREY: [u8; 3] = [128, 128, 128];
+const YELLOW: [u8; 3] = [205, 205, 0];
+
+/// Never drawn, so a front buffer filled with it redraws every cell.
+const UNKNOWN_CELL: Cell = Cell { glyph: '\0', fg: BLACK, bg: BLACK };
+
+/// Draws a cell at a view coordinate, allowing for the HUD above.
+fn set_view_cell(buffer: &mut CellBuffer, coord: &Coord, cell: Cell) {
+    if coord.x >= 0 && coord.y >= 0 {
+        buffer.set(coord.x as usize, coord.y as usize + HUD_ROWS_ABOVE, cell);
+    }
+}
+
+/// Where an entity appears in the view, if it's on screen at all and close
+/// enough to the player to be seen in the current light.
+fn entity_view_coord(map_view: &TileMapView, position: &Position, player: &Position, lighting: &Lighting) -> Option<Coord> {
+    let distance = ((position.x - player.x).powi(2) + (position.y - player.y).powi(2)).sqrt();
+    if lighting.brightness_at(distance) <= 0.0 {
+        return None;
+    }
+    let coord = map_view.world_to_view_coord(position.x.round() as usize, position.y.round() as usize);
+    if coord.x < 0 || coord.y < 0 || coord.x >= map_view.width as isize || coord.y >= map_view.height as isize {
+        return None;
+    }
+    Some(coord)
+}
+
+/// The most common tile type in a block of the map, if any of the block
+/// is on it, and how far the block's middle is from the player.
+fn block_type<'a>(
+    map: &'a TileMap,
+    x: isize,
+    y: isize,
+    width: usize,
+    height: usize,
+    player: &Player,
+) -> Option<(&'a TileType, f32)> {
+    let (right, bottom) = (x + width as isize, y + height as isize);
+    if right <= 0 || bottom <= 0 {
+        return None;
+    }
+    let (left, top) = (cmp::max(x, 0) as usize, cmp::max(y, 0) as usize);
+    let style = map.majority_style(left, top, right as usize - left, bottom as usize - top)?;
+    let centre = (x as f32 + width as f32 / 2.0, y as f32 + height as f32 / 2.0);
+    let distance = ((centre.0 - player.x as f32).powi(2) + (centre.1 - player.y as f32).powi(2)).sqrt();
+    Some((map.types.get(style), distance))
+}
+
+/// A tile type's plain glyph, or nothing if it's too dark to see.
+fn plain_glyph(tile_type: &TileType, distance: f32, lighting: &Lighting) -> char {
+    if lighting.brightness_at(distance) > 0.0 { tile_type.plain_glyph } else { ' ' }
+}
+
+/// Draws the map zoomed out. Each cell is an upper half block, coloured
+/// like the tiles of its top half, on the colour of its bottom half. Plain,
+/// each cell is the plain glyph of its tiles as a whole.
+fn draw_zoomed_tiles(
+    buffer: &mut CellBuffer,
+    map: &TileMap,
+    map_view: &TileMapView,
+    player: &Player,
+    lighting: &Lighting,
+    plain: bool,
+) {
+    let zoom = map_view.zoom;
+    let block_colour = |x: isize, y: isize| {
+        block_type(map, x, y, zoom, zoom, player).map(|(tile_type, distance)| lighting.light(tile_type.colour_bg, distance))
+    };
+    for view_y in 0..map_view.height {
+        for view_x in 0..map_view.width {
+            let corner = map_view.view_to_world_coord(view_x, view_y);
+            if plain {
+                if let Some((tile_type, distance)) = block_type(map, corner.x, corner.y, zoom, zoom * 2, player) {
+                    buffer.set(view_x, view_y + HUD_ROWS_ABOVE, Cell::new(plain_glyph(tile_type, distance, lighting), WHITE, BLACK));
+                }
+                continue;
+            }
+            let top = block_colour(corner.x, corner.y);
+            let bottom = block_colour(corner.x, corner.y + zoom as isize);
+            if top.is_some() || bottom.is_some() {
+                buffer.set(view_x, view_y + HUD_ROWS_ABOVE, Cell::new('\u{2580}', top.unwrap_or(BLACK), bottom.unwrap_or(BLACK)));
+            }
+        }
+    }
+}
+
+/// Draws the visible tiles, the player and the map details into a frame.
+/// With the map open, the details are of the tile under the cursor. Plain,
+/// every tile of a type looks the same, for telling them apart without
+/// colour. Returns the column just after the details, for anything to
+/// follow them.
+pub fn draw_map(
+    buffer: &mut CellBuffer,
+    map: &TileMap,
+    map_view: &TileMapView,
+    player: &Player,
+    rand: f32,
+    lighting: &Lighting,
+    plain: bool,
+) -> usize {
+    buffer.clear();
+    if map_view.zoom > 1 {
+        draw_zoomed_tiles(buffer, map, map_view, player, lighting, plain);
+    } else {
+        for row in map_view.get_tile_ranges().iter() {
+            for tile in map.tiles[row.start..row.end].iter() {
+                let tile_type = map.tile_type(tile);
+                let tile_coord = map_view.world_to_view_coord(tile.x, tile.y);
+                let distance = ((tile.x as f32 - player.x as f32).powi(2) + (tile.y as f32 - player.y as f32).powi(2)).sqrt();
+                let glyph = if plain {
+                    plain_glyph(tile_type, distance, lighting)
+                } else {
+                    tile_type.glyph(tile.rand_offset, rand)
+                };
+                set_view_cell(buffer, &tile_coord, Cell::new(
+                    glyph,
+                    lighting.light(tile_type.colour_fg, distance),
+                    lighting.light(tile_type.colour_bg, distance),
+                ));
+            }
+        }
+    }
+    let player_coord = map_view.world_to_view_coord(player.x, player.y);
+    set_view_cell(buffer, &player_coord, Cell::new(player_glyph(plain), WHITE, BLACK));
+    // Stamina sits at the right of the rule above the log.
+    let log_header_row = map_view.height + HUD_ROWS_ABOVE;
+    buffer.put_str(0, log_header_row, &"-".repeat(buffer.width), GREY, BLACK);
+    let filled = ((player.stamina / MAX_STAMINA * STAMINA_BAR_WIDTH as f32).round() as usize).min(STAMINA_BAR_WIDTH);
+    let stamina = format!(" run [{}{}] ", "#".repeat(filled), " ".repeat(STAMINA_BAR_WIDTH.saturating_sub(filled)));
+    let stamina_column = map_view.width.saturating_sub(stamina.len() + 2);
+    buffer.put_str(stamina_column, log_header_row, &stamina, WHITE, BLACK);
+    let details = match map_view.cursor {
+        Some((x, y)) => match map.get_tile(x, y) {
+            Some(tile) => format!("{},{} {}", x, y, map.tile_type(tile).name),
+            None => format!("{},{}", x, y),
+        },
+        None => format!("{},{} seed {}", player.x, player.y, map.seed),
+    };
+    buffer.put_str(0, 0, &details, WHITE, BLACK);
+    details.chars().count()
+}
+
+fn player_glyph(plain: bool) -> char {
+    if plain { '@' } else { '&' }
+}
+
+/// Draws the animals, the game status and the latest message into a frame
+/// already holding the map. With the map open, the map cursor and what's
+/// under it stand in for the status. Plain, animals are letters.
+pub fn draw_world(
+    buffer: &mut CellBuffer,
+    world: &World,
+    map_view: &TileMapView,
+    lighting: &Lighting,
+    plain: bool,
+) {
+    let player = world.read_resource::<PlayerPosition>().0;
+    let positions = world.read::<Position>();
+    let mut draw_entity = |position: &Position, cell: Cell| {
+        if let Some(coord) = entity_view_coord(map_view, position, &player, lighting) {
+            set_view_cell(buffer, &coord, cell);
+        }
+    };
+    let theme = world.read_resource::<Theme>();
+    let themed = |glyph: char, colours: &Colours| Cell::new(glyph, colours.colour_fg, colours.colour_bg);
+    let (sheep, dog, wolf) = if plain { ('S', 'D', 'W') } else { ('o', 'd', 'W') };
+    (&world.read::<Sheep>(), &positions).join().for_each(|(_, position)| draw_entity(position, themed(sheep, &theme.sheep)));
+    (&world.read::<Dog>(), &positions).join().for_each(|(_, position)| draw_entity(position, themed(dog, &theme.dog)));
+    (&world.read::<Wolf>(), &positions).join().for_each(|(_, position)| draw_entity(position, themed(wolf, &theme.wolf)));
+
+    let status = match map_view.cursor {
+        Some((x, y)) => {
+            let cursor = map_view.world_to_view_coord(x, y);
+            set_view_cell(buffer, &cursor, Cell::new(if plain { '?' } else { '+' }, BLACK, YELLOW));
+            let under_cursor = |position: &Position| {
+                let coord = map_view.world_to_view_coord(position.x.round() as usize, position.y.round() as usize);
+                coord.x == cursor.x && coord.y == cursor.y
+            };
+            let sheep = (&world.read::<Sheep>(), &positions).join().filter(|&(_, position)| under_cursor(position)).count();
+            let dogs = (&world.read::<Dog>(), &positions).join().filter(|&(_, position)| under_cursor(position)).count();
+            let wolves = (&world.read::<Wolf>(), &positions).join().filter(|&(_, position)| under_cursor(position)).count();
+            format!("map x{}  here: {} sheep, {} dogs, {} wolves", map_view.zoom, sheep, dogs, wolves)
+        }
+        None => game_status(world),
+    };
+    buffer.put_str(STATUS_COLUMN, 0, &status, WHITE, BLACK);
+
+    // Bubble borders would pass for tiles in plain glyphs, and speech is on
+    // the message line anyway.
+    if !plain {
+        draw_bubbles(buffer, world, map_view, lighting);
+    }
+
+    // The newest entries that fit, unless the player has scrolled back.
+    let log = world.read_resource::<EventLog>();
+    let log_header_row = map_view.height + HUD_ROWS_ABOVE;
+    let scroll = cmp::min(world.read_resource::<Hud>().log_scroll, log.0.len().saturating_sub(1));
+    let title = if scroll > 0 { format!(" log, {} newer ", scroll) } else { String::from(" log ") };
+    buffer.put_str(2, log_header_row, &title, WHITE, BLACK);
+    let end = log.0.len() - scroll;
+    for (row, entry) in log.0[end.saturating_sub(LOG_ROWS)..end].iter().enumerate() {
+        buffer.put_str(0, log_header_row + 1 + row, &entry.to_string(), WHITE, BLACK);
+    }
+
+    // The open map leaves nothing for the minimap to add.
+    if world.read_resource::<Hud>().minimap && map_view.cursor.is_none() {
+        draw_minimap(buffer, world, map_view, plain);
+    }
+
+    if world.read_resource::<Hud>().help {
+        let bindings = world.read_resource::<KeyBindings>();
+        let title = match bindings.keys_for(Action::Help).first() {
+            Some(&key) => format!("Keys ({} to close)", key_name(key)),
+            None => String::from("Keys"),
+        };
+        let mut lines = vec![title, String::new()];
+        lines.extend(bindings.help_lines());
+        draw_panel(buffer, &lines);
+    }
+}
 
-struct TermTileStyle {
-    pub colour_bg: color::Bg<color::Rgb>,
-    pub colour_fg: color::Fg<color::Rgb>,
-    pub char_gen: (fn(f32, f32) -> char),
+/// The dog's orders, the time and how the flock is doing.
+fn game_status(world: &World) -> String {
+    let clock = world.read_resource::<GameClock>();
+    let pen_count = world.read_resource::<PenCount>();
+    let speed = if clock.paused {
+        String::from("  paused")
+    } else if clock.time_scale() != 1.0 {
+        format!("  x{}", clock.time_scale())
+    } else {
+        String::new()
+    };
+    format!(
+        "dog: {}  day {} {}, {}  {}/{} sheep penned{}",
+        *world.read_resource::<DogCommand>(),
+        clock.day_number(),
+        clock.day_phase(),
+        *world.read_resource::<Weather>(),
+        pen_count.penned,
+        pen_count.total,
+        speed
+    )
 }
 
-impl TermTileStyle {
-    pub fn new(
-        colour_bg: [u8; 3],
-        colour_fg: [u8; 3],
-        char_gen: (fn(f32, f32) -> char),
-    ) -> TermTileStyle {
-        TermTileStyle {
-            colour_bg: color::Bg(color::Rgb(colour_bg[0], colour_bg[1], colour_bg[2])),
-            colour_fg: color::Fg(color::Rgb(colour_fg[0], colour_fg[1], colour_fg[2])),
-            char_gen: char_gen,
+/// Breaks text into lines no wider than `width`, at spaces where it can.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        // Words too long for a line of their own get chopped up.
+        while word.len() > width {
+            if !line.is_empty() {
+                lines.push(mem::take(&mut line));
+            }
+            lines.push(word.drain(..width).collect());
         }
+        let word: String = word.into_iter().collect();
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
     }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Draws a speech bubble over whoever said each utterance. Bubbles that
+/// would overlap stack upwards, newest nearest the speaker, and fade out as
+/// they're about to go.
+fn draw_bubbles(buffer: &mut CellBuffer, world: &World, map_view: &TileMapView, lighting: &Lighting) {
+    let player = world.read_resource::<PlayerPosition>().0;
+    let now = world.read_resource::<GameClock>().now;
+    let mut said: Vec<(Coord, &Utterance)> = Vec::new();
+    let utterances = world.read::<Utterance>();
+    let positions = world.read::<Position>();
+    for (utterance, position) in (&utterances, &positions).join() {
+        if let Some(coord) = entity_view_coord(map_view, position, &player, lighting) {
+            said.push((coord, utterance));
+        }
+    }
+    said.sort_by_key(|&(_, utterance)| cmp::Reverse(utterance.dead_at));
+
+    // Placed bubbles as (left, top, right, bottom) view coordinates.
+    let mut placed: Vec<(isize, isize, isize, isize)> = Vec::new();
+    for (speaker, utterance) in said {
+        let lines = wrap(&utterance.text, BUBBLE_TEXT_WIDTH);
+        let inner_width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+        let width = inner_width as isize + 4;
+        let height = lines.len() as isize + 2;
+        let left = (speaker.x - width / 2).max(0).min((map_view.width as isize - width).max(0));
+        let right = left + width - 1;
+        let mut bottom = speaker.y - 1;
+        while let Some(&(_, top, _, _)) = placed.iter().find(|&&(other_left, other_top, other_right, other_bottom)| {
+            left <= other_right && right >= other_left && bottom - height < other_bottom && bottom >= other_top
+        }) {
+            bottom = top - 1;
+        }
+        let top = bottom - height + 1;
+        if top < 0 {
+            continue;
+        }
+        placed.push((left, top, right, bottom));
+
+        let remaining = (utterance.dead_at - now).num_milliseconds() as f32 / BUBBLE_FADE_MILLISECONDS as f32;
+        let fade = remaining.clamp(0.25, 1.0);
+        let colour = [(255.0 * fade) as u8; 3];
+        let row = |y: isize| y as usize + HUD_ROWS_ABOVE;
+        let border = format!("+{}+", "-".repeat(width as usize - 2));
+        buffer.put_str(left as usize, row(top), &border, colour, BLACK);
+        for (offset, line) in lines.iter().enumerate() {
+            let text = format!("| {:<width$} |", line, width = inner_width);
+            buffer.put_str(left as usize, row(top + 1 + offset as isize), &text, colour, BLACK);
+        }
+        buffer.put_str(left as usize, row(bottom), &border, colour, BLACK);
+        // Point the tail at the speaker, if they're right below.
+        if bottom == speaker.y - 1 && speaker.x > left && speaker.x < right {
+            buffer.set(speaker.x as usize, row(bottom), Cell::new('v', colour, BLACK));
+        }
+    }
+}
+
+/// Draws the minimap in the top right corner of the view, marking pens,
+/// where the sheep are and the player. Plain, the terrain is plain glyphs.
+fn draw_minimap(buffer: &mut CellBuffer, world: &World, map_view: &TileMapView, plain: bool) {
+    let minimap = world.read_resource::<Minimap>();
+    if minimap.width == 0 {
+        return;
+    }
+    let left = map_view.width.saturating_sub(minimap.width + 2);
+    let top = HUD_ROWS_ABOVE;
+    let border = format!("+{}+", "-".repeat(minimap.width));
+    buffer.put_str(left, top, &border, WHITE, BLACK);
+    buffer.put_str(left, top + minimap.height + 1, &border, WHITE, BLACK);
+    for y in 0..minimap.height {
+        buffer.set(left, top + 1 + y, Cell::new('|', WHITE, BLACK));
+        buffer.set(left + minimap.width + 1, top + 1 + y, Cell::new('|', WHITE, BLACK));
+        for x in 0..minimap.width {
+            let index = y * minimap.width + x;
+            let style = minimap.types.get(minimap.styles[index]);
+            let glyph = if minimap.pens[index] {
+                // Plain, '#' would look like fencing.
+                if plain { '$' } else { '#' }
+            } else if plain {
+                style.plain_glyph
+            } else {
+                ' '
+            };
+            buffer.set(left + 1 + x, top + 1 + y, Cell::new(glyph, style.colour_fg, style.colour_bg));
+        }
+    }
+
+    // Count sheep per cell, so clusters stand out from strays.
+    let mut sheep_counts = vec![0; minimap.width * minimap.height];
+    for (_, position) in (&world.read::<Sheep>(), &world.read::<Position>()).join() {
+        if let Some((x, y)) = minimap.cell_at(position.x, position.y) {
+            sheep_counts[y * minimap.width + x] += 1;
+        }
+    }
+    for (index, &count) in sheep_counts.iter().enumerate() {
+        if count > 0 {
+            let glyph = match (count >= MINIMAP_CLUSTER, plain) {
+                (true, true) => 'S',
+                (false, true) => 's',
+                (true, false) => 'O',
+                (false, false) => 'o',
+            };
+            let cell = buffer.get(left + 1 + index % minimap.width, top + 1 + index / minimap.width).cloned();
+            let bg = cell.map_or(BLACK, |cell| cell.bg);
+            buffer.set(left + 1 + index % minimap.width, top + 1 + index / minimap.width, Cell::new(glyph, WHITE, bg));
+        }
+    }
+    let player = world.read_resource::<PlayerPosition>().0;
+    if let Some((x, y)) = minimap.cell_at(player.x, player.y) {
+        buffer.set(left + 1 + x, top + 1 + y, Cell::new(player_glyph(plain), WHITE, BLACK));
+    }
+}
+
+/// Draws a bordered box of text in the middle of the frame.
+fn draw_panel(buffer: &mut CellBuffer, lines: &[String]) {
+    let inner_width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) + 2;
+    let left = buffer.width.saturating_sub(inner_width + 2) / 2;
+    let top = buffer.height.saturating_sub(lines.len() + 2) / 2;
+    let border = format!("+{}+", "-".repeat(inner_width));
+    buffer.put_str(left, top, &border, WHITE, BLACK);
+    for (row, line) in lines.iter().enumerate() {
+        buffer.put_str(left, top + row + 1, &format!("| {:<width$} |", line, width = inner_width - 2), WHITE, BLACK);
+    }
+    buffer.put_str(left, top + lines.len() + 1, &border, WHITE, BLACK);
+}
+
+/// Writes the view in a drawn frame, without the HUD, as a web page and
+/// ANSI art.
+pub fn export_view(frame: &CellBuffer, title: &str, html_path: &str, ans_path: &str, plain: bool) -> Result<(), ExportError> {
+    let view_height = frame.height.saturating_sub(HUD_ROWS_ABOVE + HUD_ROWS_BELOW);
+    let view = frame.region(0, HUD_ROWS_ABOVE, frame.width, view_height);
+    export::write_html(&view, title, html_path, plain)?;
+    export::write_ans(&view, ans_path, plain)
+}
+
+/// What to send a terminal showing `front` to make it show `back`: only
+/// the cells that changed, with cursor moves and colour codes only where
+/// they change. The buffers must be the same size.
+fn diff(front: &CellBuffer, back: &CellBuffer, depth: ColourDepth) -> String {
+    let mut output = String::new();
+    // Track where the terminal's cursor and colours are left after each
+    // write, so moves and colour codes are only sent when they change.
+    let mut cursor_at = None;
+    let mut fg = None;
+    let mut bg = None;
+    for y in 0..back.height {
+        for x in 0..back.width {
+            let cell = *back.get(x, y).unwrap();
+            if front.get(x, y) == Some(&cell) {
+                continue;
+            }
+            if cursor_at != Some((x, y)) {
+                output.push_str(&format!("{}", cursor::Goto(x as u16 + 1, y as u16 + 1)));
+            }
+            let cell_bg = depth.reduce(cell.bg);
+            let mut cell_fg = depth.reduce(cell.fg);
+            // Fewer colours can run a glyph into its background.
+            if cell_fg == cell_bg && cell.fg != cell.bg {
+                cell_fg = cell_fg.contrast();
+            }
+            if bg != Some(cell_bg) {
+                output.push_str(&cell_bg.bg());
+                bg = Some(cell_bg);
+            }
+            if fg != Some(cell_fg) {
+                output.push_str(&cell_fg.fg());
+                fg = Some(cell_fg);
+            }
+            output.push(cell.glyph);
+            cursor_at = Some((x + 1, y));
+        }
+    }
+    output
 }
 
 pub struct Renderer {
     stdout: RefCell<RawTerminal<std::io::Stdout>>,
-    tile_styles: Vec<TermTileStyle>,
+    /// What the terminal is showing now.
+    front: RefCell<CellBuffer>,
+    /// The frame being drawn, sent to the terminal by `present`.
+    back: RefCell<CellBuffer>,
+    /// How colourful the terminal is.
+    depth: ColourDepth,
+    /// Bytes sent to the terminal for the last frame.
+    frame_bytes: std::cell::Cell<usize>,
+    /// Set by SIGWINCH whenever the terminal changes size. Starts set, so
+    /// the first check picks up the starting size.
+    resized: Arc<AtomicBool>,
 }
 
 impl Renderer {
-    pub fn new() -> Renderer {
+    pub fn new(depth: ColourDepth) -> Renderer {
         let output = std::io::stdout().into_raw_mode().unwrap();
+        let resized = Arc::new(AtomicBool::new(true));
+        signal_hook::flag::register(SIGWINCH, resized.clone()).unwrap();
         Renderer {
             stdout: RefCell::new(output),
-            tile_styles: vec![
-                // rock high
-                TermTileStyle::new([107, 103, 98], [117, 113, 107], |offset, _rand| {
-                    if offset > 0.8 { '#' } else if offset > 0.6 { '%' } else if offset > 0.4 { '=' } else { ' ' }
-                }),
-                // rock low
-                TermTileStyle::new([91, 88, 84], [117, 113, 107], |offset, _rand| {
-                    if offset > 0.8 { '#' } else if offset > 0.6 { '%' } else if offset > 0.4 { '=' } else { ' ' }
-                }),
-                // dirt
-                TermTileStyle::new([71, 56,  19], [122, 97, 33], |offset, _rand| {
-                    if offset > 0.8 { '~' } else if offset > 0.6 { 'o' } else if offset > 0.4 { '.' } else { ' ' }
-                }),
-                // dirt farmed
-                TermTileStyle::new([71, 56,  19], [136, 181, 48], |offset, _rand| {
-                    if offset > 0.8 { 'v' } else if offset > 0.5 { '\'' } else if offset > 0.25 { '.' } else { '~' }
-                }),
-                // tree
-                TermTileStyle::new([99, 130, 35], [76, 91, 47], |offset, _rand| {
-                    if offset > 0.8 { '*' } else if offset > 0.6 { 'V' } else if offset > 0.4 { ':' } else { 'Y' }
-                }),
-                // grass plain
-                TermTileStyle::new([99, 130, 35], [136, 181, 48], |offset, rand| {
-                    if offset > 0.8 { 'v' } else if offset > 0.7 { ',' } else if offset > 0.4 { '.' } else if offset > 0.15 { ' ' } else if (rand + offset) % 1.0 > 0.5 { ',' } else { '.' }
-                }),
-                // grass coastal
-                TermTileStyle::new([99, 130, 35], [136, 181, 48], |offset, rand| {
-                    if offset > 0.9 { ',' } else if offset > 0.75 { '.' } else if offset > 0.15 { ' ' } else if (rand + offset) % 1.0 > 0.5 { ',' } else { '.' }
-                }),
-                // sand
-                TermTileStyle::new([140, 134, 107], [165, 158, 127], |offset, _rand| {
-                    if offset > 0.8 { '~' } else if offset > 0.6 { '-' } else if offset > 0.4 { '.' } else { ' ' }
-                }),
-                // water shallow
-                TermTileStyle::new([84, 116, 122], [102, 141, 147], |offset, rand| {
-                    if (rand + offset) % 1.0 > 0.8 { '~' } else { ' ' }
-                }),
-                // water deep
-                TermTileStyle::new([77, 106, 112], [102, 141, 147], |offset, rand| {
-                    if (rand + offset) % 1.0 > 0.8 { '~' } else { ' ' }
-                })
-            ]
-        }
-    }
-
-    fn get_tile_style(&self, tile_style: &TileStyle) -> &TermTileStyle {
-        match *tile_style {
-            TileStyle::RockHigh     => &self.tile_styles[0],
-            TileStyle::RockLow      => &self.tile_styles[1],
-            TileStyle::Dirt         => &self.tile_styles[2],
-            TileStyle::DirtFarmed   => &self.tile_styles[3],
-            TileStyle::Tree         => &self.tile_styles[4],
-            TileStyle::GrassPlain   => &self.tile_styles[5],
-            TileStyle::GrassCoastal => &self.tile_styles[6],
-            TileStyle::Sand         => &self.tile_styles[7],
-            TileStyle::WaterShallow => &self.tile_styles[8],
-            TileStyle::WaterDeep    => &self.tile_styles[9],
+            front: RefCell::new(CellBuffer::new(0, 0)),
+            back: RefCell::new(CellBuffer::new(0, 0)),
+            depth,
+            frame_bytes: std::cell::Cell::new(0),
+            resized,
         }
     }
 }
 
 impl Renderable for Renderer {
     fn set_up(&self) {
-        write!(self.stdout.borrow_mut(), "{}", cursor::Hide).unwrap();
+        write!(self.stdout.borrow_mut(), "{}{}", cursor::Hide, clear::All).unwrap();
+    }
+
+    fn poll_resize(&self) -> Option<(usize, usize)> {
+        if !self.resized.swap(false, Ordering::Relaxed) {
+            return None;
+        }
+        let (columns, rows) = termion::terminal_size().unwrap_or((80, 40));
+        let (columns, rows) = (columns as usize, rows as usize);
+        // Whatever was on screen is gone or moved, so start from scratch.
+        self.back.borrow_mut().resize(columns, rows);
+        self.front.borrow_mut().resize(columns, rows);
+        self.front.borrow_mut().fill(UNKNOWN_CELL);
+        write!(self.stdout.borrow_mut(), "{}", clear::All).unwrap();
+        Some((columns, rows.saturating_sub(HUD_ROWS_ABOVE + HUD_ROWS_BELOW)))
     }
 
     fn render_map(
         &self,
-        ref map: &TileMap,
-        ref map_view: &TileMapView,
-        ref player: &Player,
+        map: &TileMap,
+        map_view: &TileMapView,
+        player: &Player,
         &rand: &f32,
+        lighting: &Lighting,
     ) {
-        let mut buffer = String::with_capacity(map_view.width * map_view.height * 45);
-        buffer.push_str(&format!("{}", clear::All));
-        {
-            for row in map_view.get_tile_ranges().iter() {
-                for tile in map.tiles[row.start..row.end].iter() {
-                    let tile_style = self.get_tile_style(&tile.style);
-                    let tile_coord = map_view.world_to_view_coord(tile.x, tile.y);
-                    buffer.push_str(&format!(
-                        "{}{}{}{}",
-                        cursor::Goto(tile_coord.x as u16 + 1, tile_coord.y as u16 + 1),
-                        tile_style.colour_bg,
-                        tile_style.colour_fg,
-                        (tile_style.char_gen)(tile.rand_offset, rand)
-                    ));
-                }
-            }
-        }
-        {
-            let player_coord = map_view.world_to_view_coord(player.x, player.y);
-            buffer.push_str(&format!(
-                "{}{}{}&",
-                cursor::Goto((player_coord.x + 1) as u16, (player_coord.y + 1) as u16),
-                color::Bg(color::Black),
-                color::Fg(color::White)
-            ));
-        }
-        let mut stdout = self.stdout.borrow_mut();
-        write!(stdout, "{}", buffer).unwrap();
-        write!(stdout, "{}{}", cursor::Goto(1, 1), buffer.len()).unwrap();
-        write!(stdout, "{}{}", cursor::Goto(8, 1), buffer.capacity()).unwrap();
-        write!(stdout, "{}{},{}", cursor::Goto(15, 1), player.x, player.y).unwrap();
-        stdout.flush().unwrap();
+        let mut back = self.back.borrow_mut();
+        let details_end = draw_map(&mut back, map, map_view, player, rand, lighting, self.depth == ColourDepth::Monochrome);
+        back.put_str(details_end, 0, &format!(" {}b", self.frame_bytes.get()), WHITE, BLACK);
     }
 
     fn render_world(
         &self,
-        ref world: &World,
+        world: &World,
+        map_view: &TileMapView,
+        lighting: &Lighting,
     ) {
-        world.read::<Utterance>().join().for_each(|entity| {
-            println!("Hello, {:?}", entity);
-        });
+        draw_world(&mut self.back.borrow_mut(), world, map_view, lighting, self.depth == ColourDepth::Monochrome);
+    }
+
+    fn export(&self, title: &str, html_path: &str, ans_path: &str) -> Result<(), ExportError> {
+        export_view(&self.back.borrow(), title, html_path, ans_path, self.depth == ColourDepth::Monochrome)
+    }
+
+    fn present(&self) {
+        let mut back = self.back.borrow_mut();
+        let mut front = self.front.borrow_mut();
+        let output = diff(&front, &back, self.depth);
+        let mut stdout = self.stdout.borrow_mut();
+        write!(stdout, "{}", output).unwrap();
+        stdout.flush().unwrap();
+        self.frame_bytes.set(output.len());
+        mem::swap(&mut *front, &mut *back);
     }
 
     fn tear_down(&self) {
@@ -172,3 +605,69 @@ impl Renderable for Renderer {
         write!(stdout, "{}", cursor::Show).unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use termion::cursor;
+
+    use cell_buffer::{Cell, CellBuffer};
+
+    use palette::ColourDepth;
+
+    use super::{diff, wrap, BUBBLE_TEXT_WIDTH};
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    #[test]
+    fn same_frame_sends_nothing() {
+        let mut frame = CellBuffer::new(4, 3);
+        frame.put_str(0, 1, "baa", RED, BLUE);
+        assert_eq!(diff(&frame, &frame.clone(), ColourDepth::TrueColour), "");
+    }
+
+    #[test]
+    fn one_changed_cell_sends_one_move_and_glyph() {
+        let front = CellBuffer::new(4, 3);
+        let mut back = front.clone();
+        back.set(2, 1, Cell::new('o', RED, BLUE));
+        assert_eq!(
+            diff(&front, &back, ColourDepth::TrueColour),
+            format!("{}\x1b[48;2;0;0;255m\x1b[38;2;255;0;0mo", cursor::Goto(3, 2))
+        );
+    }
+
+    #[test]
+    fn a_run_of_one_colour_sends_its_codes_once() {
+        let front = CellBuffer::new(6, 2);
+        let mut back = front.clone();
+        back.put_str(1, 0, "flock", RED, BLUE);
+        assert_eq!(
+            diff(&front, &back, ColourDepth::Ansi256),
+            format!("{}\x1b[48;5;21m\x1b[38;5;196mflock", cursor::Goto(2, 1))
+        );
+    }
+
+    #[test]
+    fn wraps_text() {
+        let cases: [(&str, usize, &[&str]); 8] = [
+            ("", 5, &[]),
+            ("baa baa", 7, &["baa baa"]),
+            ("baa baa", 6, &["baa", "baa"]),
+            ("  come   bye ", 20, &["come bye"]),
+            ("abcdefghij", 4, &["abcd", "efgh", "ij"]),
+            ("hi abcdefgh", 4, &["hi", "abcd", "efgh"]),
+            ("héé ñoño", 4, &["héé", "ñoño"]),
+            ("ééééé", 2, &["éé", "éé", "é"]),
+        ];
+        for &(text, width, lines) in cases.iter() {
+            assert_eq!(wrap(text, width), lines, "{:?} at {}", text, width);
+        }
+    }
+
+    #[test]
+    fn wraps_long_words_at_the_bubble_width() {
+        let word = "a".repeat(BUBBLE_TEXT_WIDTH + 6);
+        assert_eq!(wrap(&word, BUBBLE_TEXT_WIDTH), ["a".repeat(BUBBLE_TEXT_WIDTH), "a".repeat(6)]);
+    }
+}
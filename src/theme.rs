@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+
+use ron;
+
+use tile_types::TileTypes;
+
+pub const DEFAULT_THEME: &str = "default";
+
+/// Themes that come with the game, by name. The default one changes
+/// nothing.
+const THEMES: [(&str, &str); 3] = [
+    (DEFAULT_THEME, "()"),
+    ("high-contrast", include_str!("../data/themes/high-contrast.ron")),
+    ("colour-blind", include_str!("../data/themes/colour-blind.ron")),
+];
+
+#[derive(Debug)]
+pub struct ThemeError(String);
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<io::Error> for ThemeError {
+    fn from(error: io::Error) -> ThemeError {
+        ThemeError(error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Colours {
+    pub colour_bg: [u8; 3],
+    pub colour_fg: [u8; 3],
+}
+
+impl Colours {
+    const fn new(colour_bg: [u8; 3], colour_fg: [u8; 3]) -> Colours {
+        Colours {
+            colour_bg,
+            colour_fg,
+        }
+    }
+}
+
+/// Colours to draw the game in instead of its usual ones. Tile colours
+/// replace those in the tile types, by id; anything left out keeps its
+/// usual colours. Misspelt keys are errors rather than quietly ignored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    tiles: HashMap<String, Colours>,
+    pub sheep: Colours,
+    pub dog: Colours,
+    pub wolf: Colours,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            tiles: HashMap::new(),
+            sheep: Colours::new([255, 255, 255], [0, 0, 0]),
+            dog: Colours::new([0, 0, 0], [205, 205, 0]),
+            wolf: Colours::new([0, 0, 0], [205, 0, 0]),
+        }
+    }
+}
+
+impl Theme {
+    pub fn parse(text: &str) -> Result<Theme, ThemeError> {
+        ron::from_str(text).map_err(|error| ThemeError(error.to_string()))
+    }
+
+    /// A built-in theme by name, or else a theme file.
+    pub fn load(name: &str) -> Result<Theme, ThemeError> {
+        if let Some(&(_, text)) = THEMES.iter().find(|&&(theme, _)| theme == name) {
+            return Theme::parse(text);
+        }
+        let mut text = String::new();
+        File::open(name)?.read_to_string(&mut text)?;
+        Theme::parse(&text)
+    }
+
+    /// Recolours tile types to suit the theme.
+    pub fn apply(&self, types: &mut TileTypes) -> Result<(), ThemeError> {
+        for (id, colours) in &self.tiles {
+            let style = types.find(id).ok_or_else(|| ThemeError(format!("no '{}' tile type to colour", id)))?;
+            let tile_type = types.get_mut(style);
+            tile_type.colour_bg = colours.colour_bg;
+            tile_type.colour_fg = colours.colour_fg;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Theme, THEMES};
+
+    #[test]
+    fn built_in_themes_parse() {
+        for &(name, _) in THEMES.iter() {
+            assert!(Theme::load(name).is_ok(), "{} theme", name);
+        }
+    }
+
+    #[test]
+    fn rejects_misspelt_keys() {
+        assert!(Theme::parse("(shep: (colour_bg: (0, 0, 0), colour_fg: (0, 0, 0)))").is_err());
+        assert!(Theme::parse("(sheep: (colour_bg: (0, 0, 0), colour_fgg: (0, 0, 0)))").is_err());
+        assert_eq!(Theme::parse("(sheep: (colour_bg: (0, 0, 0), colour_fg: (1, 2, 3)))").unwrap().sheep.colour_fg, [1, 2, 3]);
+    }
+}
@@ -1,18 +1,13 @@
 use std::ops::Range;
 use std::cmp;
+use std::sync::Arc;
 
-pub enum TileStyle {
-    RockHigh,
-    RockLow,
-    Dirt,
-    DirtFarmed,
-    Tree,
-    GrassPlain,
-    GrassCoastal,
-    Sand,
-    WaterShallow,
-    WaterDeep,
-}
+use tile_types::{TileType, TileTypes};
+
+/// Which kind of tile something is, as an index into its map's
+/// `TileTypes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileStyle(pub u16);
 
 pub struct Coord {
     pub x: isize,
@@ -29,21 +24,29 @@ pub struct Tile {
 pub struct TileMap {
     pub width: usize,
     pub height: usize,
+    pub seed: u32,
+    /// Bumped every time a tile changes after generation, so anything
+    /// derived from the map knows when to recalculate.
+    pub revision: u32,
+    pub types: Arc<TileTypes>,
     pub tiles: Vec<Tile>,
 }
 
 impl TileMap {
-    pub fn new(width: usize, height: usize) -> TileMap {
+    pub fn new(width: usize, height: usize, seed: u32, types: Arc<TileTypes>) -> TileMap {
         TileMap {
-            width: width,
-            height: height,
+            width,
+            height,
+            seed,
+            revision: 0,
+            types,
             tiles: Vec::new(),
         }
     }
 
-    pub fn fill_tiles<F>(&mut self, factory: F)
+    pub fn fill_tiles<F>(&mut self, mut factory: F)
     where
-        F: Fn(usize, usize) -> Tile,
+        F: FnMut(usize, usize) -> Tile,
     {
         for y in 0..self.height {
             for x in 0..self.width {
@@ -59,11 +62,53 @@ impl TileMap {
         }
         Some(&self.tiles[index])
     }
+
+    /// What kind of tile a tile is.
+    pub fn tile_type(&self, tile: &Tile) -> &TileType {
+        self.types.get(tile.style)
+    }
+
+    /// The most common style of tile in a block of the map, if any of the
+    /// block is on it.
+    pub fn majority_style(&self, x: usize, y: usize, width: usize, height: usize) -> Option<TileStyle> {
+        let mut counts: Vec<(TileStyle, usize)> = Vec::new();
+        for y in y..cmp::min(y + height, self.height) {
+            for x in x..cmp::min(x + width, self.width) {
+                let style = self.tiles[y * self.width + x].style;
+                match counts.iter_mut().find(|&&mut (counted, _)| counted == style) {
+                    Some(count) => count.1 += 1,
+                    None => counts.push((style, 1)),
+                }
+            }
+        }
+        counts.iter().max_by_key(|&&(_, count)| count).map(|&(style, _)| style)
+    }
+
+    /// Swaps in a whole new map, e.g. one loaded from a save. This counts
+    /// as a change to every tile.
+    pub fn replace(&mut self, map: TileMap) {
+        let revision = self.revision + 1;
+        *self = map;
+        self.revision = revision;
+    }
+
+    pub fn set_tile_style(&mut self, x: usize, y: usize, style: TileStyle) {
+        let index = (y * self.width) + x;
+        if index < self.tiles.len() {
+            self.tiles[index].style = style;
+            self.revision += 1;
+        }
+    }
 }
 
 pub struct TileMapView {
     pub width: usize,
     pub height: usize,
+    /// Map tiles across each view cell. Zoomed out, a cell is split into
+    /// top and bottom halves, each `zoom` tiles square.
+    pub zoom: usize,
+    /// The tile being inspected, when browsing the map.
+    pub cursor: Option<(usize, usize)>,
     x: isize,
     y: isize,
     map_width: usize,
@@ -73,8 +118,10 @@ pub struct TileMapView {
 impl TileMapView {
     pub fn new(map: &TileMap, width: usize, height: usize) -> TileMapView {
         TileMapView {
-            width: width,
-            height: height,
+            width,
+            height,
+            zoom: 1,
+            cursor: None,
             x: 0,
             y: 0,
             map_width: map.width,
@@ -82,6 +129,18 @@ impl TileMapView {
         }
     }
 
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Catches up with the size of the map, which changes when a game is
+    /// loaded.
+    pub fn follow_map(&mut self, map: &TileMap) {
+        self.map_width = map.width;
+        self.map_height = map.height;
+    }
+
     pub fn get_tile_ranges(&self) -> Vec<Range<usize>> {
         let mut ranges = Vec::new();
         let x_offset;
@@ -93,32 +152,45 @@ impl TileMapView {
             rows = cmp::min(self.height as isize, self.map_height as isize - self.y);
         } else {
             y_offset = 0;
-            rows = self.height as isize + self.y;
+            rows = cmp::min(self.height as isize + self.y, self.map_height as isize);
         }
         if self.x > 0 {
             x_offset = self.x;
             cols = cmp::min(self.width as isize, self.map_width as isize - self.x);
         } else {
             x_offset = 0;
-            cols = self.width as isize + self.x;
+            cols = cmp::min(self.width as isize + self.x, self.map_width as isize);
         }
 
-        for y in 0..rows as isize {
+        for y in 0..rows {
             let left_i = ((y_offset + y) * self.map_width as isize) + x_offset;
             ranges.push((left_i as usize)..((left_i + cols) as usize));
         }
         ranges
     }
 
+    /// Map tiles down each view cell.
+    pub fn cell_height(&self) -> usize {
+        if self.zoom > 1 { self.zoom * 2 } else { 1 }
+    }
+
+    /// The map tile at the top left of a view cell.
+    pub fn view_to_world_coord(&self, x: usize, y: usize) -> Coord {
+        Coord {
+            x: self.x + (x * self.zoom) as isize,
+            y: self.y + (y * self.cell_height()) as isize,
+        }
+    }
+
     pub fn world_to_view_coord(&self, x: usize, y: usize) -> Coord {
         Coord {
-            x: (x as isize - self.x),
-            y: (y as isize - self.y),
+            x: (x as isize - self.x).div_euclid(self.zoom as isize),
+            y: (y as isize - self.y).div_euclid(self.cell_height() as isize),
         }
     }
 
     pub fn centre_on_map_point(&mut self, x: usize, y: usize) {
-        self.x = x as isize - (self.width / 2) as isize;
-        self.y = y as isize - (self.height / 2) as isize;
+        self.x = x as isize - (self.width / 2 * self.zoom) as isize;
+        self.y = y as isize - (self.height / 2 * self.cell_height()) as isize;
     }
 }
@@ -0,0 +1,219 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+
+use ron;
+
+use tile::TileStyle;
+
+/// The tile types the game comes with, used unless a tiles file is given.
+const DEFAULT_TILE_TYPES: &str = include_str!("../data/tiles.ron");
+
+/// Plain glyphs the game draws over the map itself: the player, the map
+/// cursor and pens on the minimap.
+const RESERVED_PLAIN_GLYPHS: [char; 3] = ['@', '?', '$'];
+
+/// Tile types the game places or builds itself, which every tiles file
+/// has to define.
+const REQUIRED_IDS: [&str; 13] = [
+    "rock-high",
+    "rock-low",
+    "dirt",
+    "dirt-farmed",
+    "tree",
+    "grass-plain",
+    "grass-coastal",
+    "sand",
+    "water-shallow",
+    "water-deep",
+    "fence",
+    "gate",
+    "gate-open",
+];
+
+#[derive(Debug)]
+pub struct TileTypesError(String);
+
+impl fmt::Display for TileTypesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<io::Error> for TileTypesError {
+    fn from(error: io::Error) -> TileTypesError {
+        TileTypesError(error.to_string())
+    }
+}
+
+fn open_ground() -> f32 {
+    1.0
+}
+
+/// One kind of tile: how it looks and how animals get on with it. See the
+/// tiles file for what each field means.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TileType {
+    /// What the game and tiles files call it.
+    pub id: String,
+    /// What the player calls it.
+    pub name: String,
+    pub colour_bg: [u8; 3],
+    pub colour_fg: [u8; 3],
+    glyphs: Vec<(f32, char)>,
+    #[serde(default)]
+    shimmer: Vec<(f32, char)>,
+    glyph: char,
+    pub plain_glyph: char,
+    pub passable: bool,
+    #[serde(default = "open_ground")]
+    pub movement_cost: f32,
+    #[serde(default)]
+    pub grazing: f32,
+}
+
+impl TileType {
+    /// The glyph for a tile with some random offset, in a frame with some
+    /// random number.
+    pub fn glyph(&self, offset: f32, rand: f32) -> char {
+        let above = |thresholds: &[(f32, char)], value: f32| {
+            thresholds.iter().find(|&&(threshold, _)| value > threshold).map(|&(_, glyph)| glyph)
+        };
+        above(&self.glyphs, offset)
+            .or_else(|| above(&self.shimmer, (rand + offset) % 1.0))
+            .unwrap_or(self.glyph)
+    }
+}
+
+/// Every kind of tile a map can have, in the order `TileStyle` numbers
+/// them.
+#[derive(Debug)]
+pub struct TileTypes(Vec<TileType>);
+
+impl TileTypes {
+    pub fn parse(text: &str) -> Result<TileTypes, TileTypesError> {
+        let types: Vec<TileType> = ron::from_str(text).map_err(|error| TileTypesError(error.to_string()))?;
+        if types.len() > u16::MAX as usize {
+            return Err(TileTypesError(format!("{} tile types is too many", types.len())));
+        }
+        for (index, tile_type) in types.iter().enumerate() {
+            if types[..index].iter().any(|earlier| earlier.id == tile_type.id) {
+                return Err(TileTypesError(format!("'{}' is defined twice", tile_type.id)));
+            }
+            if tile_type.plain_glyph.is_alphabetic() {
+                return Err(TileTypesError(format!("'{}' has a letter for a plain glyph", tile_type.id)));
+            }
+            if RESERVED_PLAIN_GLYPHS.contains(&tile_type.plain_glyph) {
+                return Err(TileTypesError(format!("'{}' has a plain glyph the game keeps for itself", tile_type.id)));
+            }
+            if let Some(earlier) = types[..index].iter().find(|earlier| earlier.plain_glyph == tile_type.plain_glyph) {
+                return Err(TileTypesError(format!("'{}' has the same plain glyph as '{}'", tile_type.id, earlier.id)));
+            }
+            if tile_type.movement_cost < 1.0 {
+                return Err(TileTypesError(format!("'{}' has a movement cost below 1", tile_type.id)));
+            }
+        }
+        for id in REQUIRED_IDS.iter() {
+            if !types.iter().any(|tile_type| tile_type.id == *id) {
+                return Err(TileTypesError(format!("'{}' isn't defined", id)));
+            }
+        }
+        Ok(TileTypes(types))
+    }
+
+    pub fn read(path: &str) -> Result<TileTypes, TileTypesError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        TileTypes::parse(&text)
+    }
+
+    pub fn get(&self, style: TileStyle) -> &TileType {
+        &self.0[style.0 as usize]
+    }
+
+    pub fn get_mut(&mut self, style: TileStyle) -> &mut TileType {
+        &mut self.0[style.0 as usize]
+    }
+
+    pub fn find(&self, id: &str) -> Option<TileStyle> {
+        self.0.iter().position(|tile_type| tile_type.id == id).map(|index| TileStyle(index as u16))
+    }
+
+    /// The style of a tile type the game relies on having.
+    pub fn style(&self, id: &str) -> TileStyle {
+        self.find(id).unwrap()
+    }
+
+    /// Every tile type's id, in style order.
+    pub fn ids(&self) -> Vec<String> {
+        self.0.iter().map(|tile_type| tile_type.id.clone()).collect()
+    }
+}
+
+impl Default for TileTypes {
+    fn default() -> TileTypes {
+        TileTypes::parse(DEFAULT_TILE_TYPES).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TileTypes, DEFAULT_TILE_TYPES};
+
+    /// The error from parsing the built-in tile types with one bit changed.
+    fn error(from: &str, to: &str) -> String {
+        assert!(DEFAULT_TILE_TYPES.contains(from));
+        TileTypes::parse(&DEFAULT_TILE_TYPES.replacen(from, to, 1)).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn built_in_types_parse() {
+        assert!(TileTypes::parse(DEFAULT_TILE_TYPES).is_ok());
+    }
+
+    #[test]
+    fn rejects_duplicate_ids() {
+        assert_eq!(error("id: \"dirt-farmed\"", "id: \"dirt\""), "'dirt' is defined twice");
+    }
+
+    #[test]
+    fn rejects_missing_required_ids() {
+        assert_eq!(error("id: \"gate-open\"", "id: \"stile\""), "'gate-open' isn't defined");
+    }
+
+    #[test]
+    fn rejects_cheap_movement() {
+        assert_eq!(error("movement_cost: 2.0", "movement_cost: 0.5"), "'rock-high' has a movement cost below 1");
+    }
+
+    #[test]
+    fn rejects_letters_for_plain_glyphs() {
+        assert_eq!(error("plain_glyph: '^'", "plain_glyph: 'R'"), "'rock-high' has a letter for a plain glyph");
+    }
+
+    #[test]
+    fn rejects_reserved_plain_glyphs() {
+        for glyph in ['@', '?', '$'] {
+            let to = format!("plain_glyph: '{}'", glyph);
+            assert_eq!(error("plain_glyph: '^'", &to), "'rock-high' has a plain glyph the game keeps for itself");
+        }
+    }
+
+    #[test]
+    fn rejects_shared_plain_glyphs() {
+        assert_eq!(error("plain_glyph: '%'", "plain_glyph: '^'"), "'rock-low' has the same plain glyph as 'rock-high'");
+    }
+
+    #[test]
+    fn rejects_too_many_types() {
+        let one = "(id: \"x\", name: \"x\", colour_bg: (0, 0, 0), colour_fg: (0, 0, 0), glyphs: [], glyph: ' ', plain_glyph: ' ', passable: true),";
+        let text = format!("[{}]", one.repeat(u16::MAX as usize + 1));
+        assert_eq!(TileTypes::parse(&text).err().unwrap().to_string(), "65536 tile types is too many");
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(TileTypes::parse("[(id: \"dirt\")]").is_err());
+    }
+}